@@ -0,0 +1,88 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::game_core::GameCore;
+use crate::game::map::Map;
+
+/// Bumped whenever the saved state changes shape.
+pub const CHECKPOINT_VERSION: u32 = 7;
+
+/// Server state saved to disk, to pick a match back up after a crash.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Checkpoint {
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    pub game: GameCore,
+    /// Board used when the arena isn't generated, which the game state
+    /// leaves out.
+    pub loaded_map: Map,
+}
+
+/// Same layout as `Checkpoint`, for saving without cloning the game.
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    version: u32,
+    saved_at: u64,
+    game: &'a GameCore,
+    loaded_map: &'a Map,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    InvalidData(String),
+    UnsupportedVersion(u32),
+    /// The game was saved running at another tick rate than the server's.
+    TickRateMismatch {
+        saved: u64,
+        configured: u64,
+    },
+}
+
+impl Checkpoint {
+    /// Saves `game` to `path`. The file is replaced in one go, so a crash
+    /// while saving leaves the previous checkpoint intact.
+    pub fn save(path: &Path, game: &GameCore) -> Result<(), CheckpointError> {
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let checkpoint = CheckpointRef {
+            version: CHECKPOINT_VERSION,
+            saved_at,
+            game,
+            loaded_map: game.loaded_map(),
+        };
+        let data = postcard::to_extend(&checkpoint, Vec::new())
+            .map_err(|err| CheckpointError::InvalidData(err.to_string()))?;
+
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, data).map_err(CheckpointError::Io)?;
+        fs::rename(&temp_path, path).map_err(CheckpointError::Io)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let data = fs::read(path).map_err(CheckpointError::Io)?;
+
+        // the version comes first, so it can be checked before the rest
+        let (version, _) = postcard::take_from_bytes::<u32>(&data)
+            .map_err(|err| CheckpointError::InvalidData(err.to_string()))?;
+        if version != CHECKPOINT_VERSION {
+            Err(CheckpointError::UnsupportedVersion(version))?
+        }
+
+        postcard::from_bytes(&data).map_err(|err| CheckpointError::InvalidData(err.to_string()))
+    }
+
+    /// The saved game, ready to be played on.
+    pub fn into_game(self) -> GameCore {
+        let mut game = self.game;
+        game.resume(self.loaded_map);
+        game
+    }
+}
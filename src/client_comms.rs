@@ -1,8 +1,8 @@
 use crate::Comms;
 use crate::comms::CommError;
-use crate::game::game_core::GameCore;
 use crate::ifc::*;
 use crate::snake_cfg::*;
+use std::path::PathBuf;
 
 
 #[derive(Debug)]
@@ -15,10 +15,12 @@ pub enum ClientError {
 pub struct ClientSettings {
     pub nickname: String,
     pub server_ip: String,
+    /// Where to dump both states when the prediction goes out of step.
+    pub desync_dump_dir: Option<PathBuf>,
 }
 
 impl ClientSettings {
-    pub fn print(self: &Self) {
+    pub fn print(&self) {
         println!(
             "[Info]: server_address: {}, nickname '{}'",
             self.server_ip, self.nickname
@@ -47,7 +49,8 @@ impl ClientComms {
             .map_err(|_| ClientError::ConnectionError)
     }
 
-    pub fn join_server(&mut self) -> Result<(), ClientError> {
+    /// Returns the server's message of the day, if it has one.
+    pub fn join_server(&mut self) -> Result<Option<String>, ClientError> {
         let register_msg = Message::JoinLobby {
             player_name: self.settings.nickname.clone(),
         };
@@ -58,8 +61,9 @@ impl ClientComms {
             .receive_message()
             .map_err(|_| ClientError::ConnectionError)?;
 
-        match response {
-            Message::Ok => Ok(()),
+        let motd = match response {
+            Message::Ok => Ok(None),
+            Message::Welcome { motd } => Ok(Some(motd)),
             Message::Nok { error_msg: msg } => {
                 eprintln!("[ERROR]: Failed to join lobby: {}", msg);
                 Err(ClientError::Unknown("".to_string()))
@@ -67,7 +71,7 @@ impl ClientComms {
             _ => Err(ClientError::Unknown("Unexpected message received".to_string())),
         }?;
 
-        Ok(())
+        Ok(motd)
     }
 
     pub fn send_input(&mut self, c: char) -> Result<(), ClientError> {
@@ -78,23 +82,24 @@ impl ClientComms {
         })
     }
 
-    pub fn receive_game_update(&mut self) -> Result<Option<GameCore>, ClientError> {
+    /// Receives the next game update or batch of events, if one arrived.
+    pub fn receive_server_update(&mut self) -> Result<Option<Message>, ClientError> {
         let response = self.comms.receive_message();
         match response {
-            Ok(response) => {
-                if let Message::GameUpdate(new_state) = response {
+            Ok(response) => match response {
+                Message::GameUpdate(_) => {
                     self.update_count += 1;
-                    if self.update_count % TICK_RATE_FREQ as usize == 0 {
+                    if self.update_count.is_multiple_of(TICK_RATE_FREQ as usize) {
                         println!("Received {} updates!", TICK_RATE_FREQ);
                     }
 
-                    return Ok(Some(new_state));
-                } else {
-                    return Err(ClientError::Unknown("Got invalid message".to_string()));
+                    Ok(Some(response))
                 }
-            }
+                Message::GameEvents(_) | Message::StateHash { .. } => Ok(Some(response)),
+                _ => Err(ClientError::Unknown("Got invalid message".to_string())),
+            },
             Err(CommError::WouldBlock) => Ok(None),
-            Err(_) => return Err(ClientError::ConnectionError),
+            Err(_) => Err(ClientError::ConnectionError),
         }
     }
 }
@@ -12,8 +12,8 @@ impl MyVec2
 {
     pub fn new(x: f32, y: f32) -> Self {
         Self {
-            x: x, 
-            y: y
+            x, 
+            y
         }
     }
 }
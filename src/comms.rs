@@ -1,4 +1,5 @@
 use std::{
+    collections::VecDeque,
     io::{self, Read, Write},
     net::TcpStream,
 };
@@ -7,8 +8,12 @@ use crate::ifc::Message;
 
 pub struct Comms {
     pub connection: Option<TcpStream>,
-    recv_buffer: [u8; BUFF_SIZE],
-    recv_buffer_pointer: usize,
+    /// Received bytes not yet making up a whole frame.
+    recv_buffer: Vec<u8>,
+    /// Whole frames received but not handed out yet, oldest first.
+    recv_queue: VecDeque<Vec<u8>>,
+    /// Frames waiting for the socket to accept them.
+    send_buffer: Vec<u8>,
 }
 
 // Define our error types. These may be customized for our error handling cases.
@@ -20,11 +25,17 @@ pub enum CommError {
     Disconnected,
     InvalidData,
     WouldBlock,
-    WaitingForMoreData,
+    /// A message that doesn't fit in a frame, with its size in bytes.
+    MessageTooLarge(usize),
 }
 
-const BUFF_SIZE: usize = 4096;
+const READ_CHUNK_SIZE: usize = 16384;
 const PREFIX_SIZE: usize = 4;
+/// Largest frame sent or accepted; anything bigger is a bug or garbage.
+const FRAME_SIZE_MAX: usize = 1 << 20;
+/// Unsent bytes kept for a peer that isn't reading before messages to it
+/// are dropped.
+const SEND_BUFFER_MAX: usize = 4 * FRAME_SIZE_MAX;
 
 fn read_into_buff(connection: &mut TcpStream, dst: &mut [u8]) -> Result<usize, CommError> {
     let bytes = connection.read(dst).map_err(|err| {
@@ -44,40 +55,39 @@ fn read_into_buff(connection: &mut TcpStream, dst: &mut [u8]) -> Result<usize, C
     }
 }
 
-fn get_last_message(buff: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
-    let mut ret: Vec<u8> = Vec::new();
-    let mut remainder: Option<Vec<u8>> = None;
-
+/// Moves every complete frame at the front of `buff` to `queue`, leaving
+/// a trailing partial frame in place.
+fn take_messages(buff: &mut Vec<u8>, queue: &mut VecDeque<Vec<u8>>) -> Result<(), CommError> {
     let mut buffer_pointer = 0;
-    let mut bytes_left = buff.len();
-    while bytes_left > PREFIX_SIZE {
+    while buff.len() - buffer_pointer >= PREFIX_SIZE {
         let mut prefix_buffer: [u8; PREFIX_SIZE] = [0; PREFIX_SIZE];
         prefix_buffer.copy_from_slice(&buff[buffer_pointer..buffer_pointer + PREFIX_SIZE]);
-        buffer_pointer += PREFIX_SIZE;
-        bytes_left -= PREFIX_SIZE;
-
         let frame_len = u32::from_be_bytes(prefix_buffer) as usize;
 
-        if frame_len > bytes_left {
-            buffer_pointer -= PREFIX_SIZE;
-            remainder = Some(buff[buffer_pointer..].to_vec());
+        if frame_len > FRAME_SIZE_MAX {
+            eprintln!("[ERROR] incoming frame of {} bytes is too large", frame_len);
+            return Err(CommError::InvalidData);
+        }
+        let frame_end = buffer_pointer + PREFIX_SIZE + frame_len;
+        if frame_end > buff.len() {
             break;
         }
-        ret = buff[buffer_pointer..buffer_pointer + frame_len as usize].to_vec();
 
-        buffer_pointer += frame_len;
-        bytes_left -= frame_len;
+        queue.push_back(buff[buffer_pointer + PREFIX_SIZE..frame_end].to_vec());
+        buffer_pointer = frame_end;
     }
 
-    (ret, remainder)
+    buff.drain(..buffer_pointer);
+    Ok(())
 }
 
 impl Comms {
     pub fn new(connection: Option<TcpStream>) -> Self {
         Self {
             connection,
-            recv_buffer: [0; BUFF_SIZE],
-            recv_buffer_pointer: 0,
+            recv_buffer: Vec::new(),
+            recv_queue: VecDeque::new(),
+            send_buffer: Vec::new(),
         }
     }
 
@@ -105,30 +115,20 @@ impl Comms {
             .expect("failed to set non blocking on a socket");
     }
 
+    /// Returns the oldest frame not handed out yet, reading until a whole
+    /// one is in. On a non-blocking socket that gives `WouldBlock` once
+    /// everything that arrived so far has been handed out.
     fn receive_message_raw(&mut self) -> Result<Vec<u8>, CommError> {
-        let recv_buff = self.recv_buffer.as_mut_slice();
-        let recv_buff_p = self.recv_buffer_pointer;
-        let read_size = read_into_buff(
-            self.connection.as_mut().unwrap(),
-            &mut recv_buff[recv_buff_p..],
-        )?;
-
-        let (last_msg, remainder) = get_last_message(&mut recv_buff[..recv_buff_p + read_size]);
-
-        if let Some(remainder) = remainder {
-            println!("got remainder...");
-            let target = &mut self.recv_buffer[..remainder.len()];
-            target.copy_from_slice(remainder.as_slice());
-            self.recv_buffer_pointer += remainder.len();
-
-            if last_msg.len() == 0 {
-                return Err(CommError::WaitingForMoreData);
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(frame) = self.recv_queue.pop_front() {
+                return Ok(frame);
             }
-        } else {
-            self.recv_buffer_pointer = 0;
-        }
 
-        Ok(last_msg)
+            let read_size = read_into_buff(self.connection.as_mut().unwrap(), &mut chunk)?;
+            self.recv_buffer.extend_from_slice(&chunk[..read_size]);
+            take_messages(&mut self.recv_buffer, &mut self.recv_queue)?;
+        }
     }
 
     pub fn receive_message(&mut self) -> Result<Message, CommError> {
@@ -147,36 +147,146 @@ impl Comms {
         Ok(deserialized)
     }
 
-    fn serialize_message(message: &Message, buf: &mut [u8]) -> Result<u32, CommError> {
-        let serialized = postcard::to_slice(message, buf).map_err(|err| {
+    fn serialize_message(message: &Message) -> Result<Vec<u8>, CommError> {
+        // room for the length prefix, filled in once the size is known
+        let mut frame = postcard::to_extend(message, vec![0; PREFIX_SIZE]).map_err(|err| {
             eprintln!("[ERROR] failed to serialize data: {}", err);
             CommError::InvalidData
         })?;
 
-        Ok(serialized.len() as u32)
+        let frame_len = frame.len() - PREFIX_SIZE;
+        if frame_len > FRAME_SIZE_MAX {
+            return Err(CommError::MessageTooLarge(frame_len));
+        }
+        frame[..PREFIX_SIZE].copy_from_slice(&(frame_len as u32).to_be_bytes());
+        Ok(frame)
     }
 
+    /// Queues a message and sends as much of the queue as the socket takes.
+    /// A frame is either queued whole or, with `WouldBlock` while the peer
+    /// is too far behind, not at all, so the stream never gets out of step.
     pub fn send_message(&mut self, message: &Message) -> Result<(), CommError> {
-        let mut buffer: [u8; BUFF_SIZE] = [0; BUFF_SIZE];
-
-        let serialized_len = Self::serialize_message(message, &mut buffer[4..BUFF_SIZE])?;
-        buffer[0..4].copy_from_slice(&serialized_len.to_be_bytes());
-
-        let total_len = serialized_len as usize + 4;
+        let frame = Self::serialize_message(message)?;
+        if self.send_buffer.len() + frame.len() > SEND_BUFFER_MAX {
+            return Err(CommError::WouldBlock);
+        }
+        self.send_buffer.extend_from_slice(&frame);
+        self.flush()
+    }
 
-        self.connection
-            .as_ref()
-            .unwrap()
-            .write_all(&buffer[..total_len])
-            .map_err(|err| {
-                if err.kind() == io::ErrorKind::WouldBlock {
-                    CommError::WouldBlock
-                } else {
+    fn flush(&mut self) -> Result<(), CommError> {
+        let connection = self.connection.as_mut().unwrap();
+        while !self.send_buffer.is_empty() {
+            match connection.write(&self.send_buffer) {
+                Ok(0) => return Err(CommError::Disconnected),
+                Ok(written) => {
+                    self.send_buffer.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
                     eprintln!("[ERROR] write failed {}", err);
-                    CommError::Unknown
+                    return Err(CommError::Unknown);
                 }
-            })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    use crate::game::desync::DesyncCheck;
+    use crate::game::game_core::GameCore;
+
+    /// A connected pair: `Comms` on one end, the raw stream on the other.
+    fn connected() -> (Comms, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut comms = Comms::new(None);
+        comms
+            .connect(&listener.local_addr().unwrap().to_string())
+            .unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        (comms, peer)
+    }
+
+    fn frame(input: char) -> Vec<u8> {
+        Comms::serialize_message(&Message::SendInput(input)).unwrap()
+    }
+
+    fn received_input(comms: &mut Comms) -> char {
+        match comms.receive_message().unwrap() {
+            Message::SendInput(c) => c,
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn every_frame_of_a_single_write_is_received() {
+        let (mut comms, mut peer) = connected();
+        peer.write_all(&[frame('a'), frame('b')].concat()).unwrap();
+
+        assert_eq!(received_input(&mut comms), 'a');
+        assert_eq!(received_input(&mut comms), 'b');
+
+        comms.set_nonblocking();
+        assert!(matches!(
+            comms.receive_message(),
+            Err(CommError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    fn frame_split_across_writes_is_received_whole() {
+        let (mut comms, mut peer) = connected();
+        comms.set_nonblocking();
+        let bytes = frame('x');
+        let (head, tail) = bytes.split_at(PREFIX_SIZE + 1);
+
+        peer.write_all(head).unwrap();
+        peer.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(matches!(
+            comms.receive_message(),
+            Err(CommError::WouldBlock)
+        ));
+
+        peer.write_all(tail).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(received_input(&mut comms), 'x');
+    }
 
-            Ok(())
+    #[test]
+    fn state_hash_arrives_with_its_update() {
+        let (mut client, peer) = connected();
+        let mut server = Comms::new(Some(peer));
+        let game = GameCore::with_seed(true, 1);
+        let mut desync = DesyncCheck::new(None);
+        desync.record_prediction(&game);
+
+        // sent back to back, the way the server sends every update
+        server
+            .send_message(&Message::StateHash {
+                match_tick: game.match_tick,
+                hash: game.state_hash(),
+            })
+            .unwrap();
+        server
+            .send_message(&Message::GameUpdate(Box::new(game.clone())))
+            .unwrap();
+
+        let Message::StateHash { match_tick, hash } = client.receive_message().unwrap() else {
+            panic!("expected the state hash first");
+        };
+        desync.check(match_tick, hash);
+        let Message::GameUpdate(update) = client.receive_message().unwrap() else {
+            panic!("expected the game update after the hash");
+        };
+
+        assert_eq!(update.state_hash(), hash);
+        assert_eq!((desync.checked, desync.divergences), (1, 0));
     }
 }
@@ -0,0 +1,473 @@
+use std::collections::VecDeque;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+use crate::map::{Cell, Map, SpawnPoint, Tile, Zone, default_spawns};
+use crate::snake::Direction;
+use crate::snake_cfg::PLAYER_COUNT_MAX;
+
+const SPAWN_CLEARANCE: i32 = 2;
+const CELLS_PER_BLOCK_PAIR: i32 = 60;
+const BLOCK_SIZE_MAX: i32 = 3;
+const ROOM_COUNT: usize = 6;
+const ROOM_ATTEMPTS: usize = 200;
+const MAZE_PATH_WIDTH: i32 = 2;
+const MAZE_PITCH: i32 = MAZE_PATH_WIDTH + 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ArenaGenerator {
+    ScatteredBlocks,
+    RoomsAndCorridors,
+    Maze,
+}
+
+impl ArenaGenerator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArenaGenerator::ScatteredBlocks => "scattered blocks",
+            ArenaGenerator::RoomsAndCorridors => "rooms and corridors",
+            ArenaGenerator::Maze => "maze",
+        }
+    }
+
+    /// Builds the arena for `seed`. The same seed always yields the same
+    /// layout, and every free cell is reachable from every spawn point.
+    pub fn generate(&self, seed: u64, width: i32, height: i32) -> Map {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (mut layout, spawns) = match self {
+            ArenaGenerator::ScatteredBlocks => scattered_blocks(&mut rng, width, height),
+            ArenaGenerator::RoomsAndCorridors => rooms_and_corridors(&mut rng, width, height),
+            ArenaGenerator::Maze => maze(&mut rng, width, height),
+        };
+
+        layout.connect(&spawns);
+        layout.into_map(format!("{} #{}", self.name(), seed), spawns)
+    }
+}
+
+struct Layout {
+    width: i32,
+    height: i32,
+    tiles: Vec<Tile>,
+}
+
+impl Layout {
+    fn new(width: i32, height: i32, fill: Tile) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![fill; (width * height) as usize],
+        }
+    }
+
+    fn index(&self, cell: Cell) -> usize {
+        (cell.y * self.width + cell.x) as usize
+    }
+
+    fn get(&self, cell: Cell) -> Tile {
+        self.tiles[self.index(cell)]
+    }
+
+    fn set(&mut self, cell: Cell, tile: Tile) {
+        let index = self.index(cell);
+        self.tiles[index] = tile;
+    }
+
+    fn fill(&mut self, zone: &Zone, tile: Tile) {
+        for y in zone.y..zone.y + zone.height {
+            for x in zone.x..zone.x + zone.width {
+                self.set(Cell::new(x, y), tile);
+            }
+        }
+    }
+
+    fn add_border(&mut self) {
+        for x in 0..self.width {
+            self.set(Cell::new(x, 0), Tile::Wall);
+            self.set(Cell::new(x, self.height - 1), Tile::Wall);
+        }
+        for y in 0..self.height {
+            self.set(Cell::new(0, y), Tile::Wall);
+            self.set(Cell::new(self.width - 1, y), Tile::Wall);
+        }
+    }
+
+    fn wrap(&self, cell: Cell) -> Cell {
+        Cell::new(
+            cell.x.rem_euclid(self.width),
+            cell.y.rem_euclid(self.height),
+        )
+    }
+
+    /// Marks every free cell reachable from `origin`, following the same
+    /// edge wrapping as snake movement.
+    fn flood(&self, origin: Cell) -> Vec<bool> {
+        let mut reached = vec![false; self.tiles.len()];
+        let mut queue = VecDeque::from([origin]);
+        reached[self.index(origin)] = true;
+
+        while let Some(cell) = queue.pop_front() {
+            for direction in [
+                Direction::Up,
+                Direction::Down,
+                Direction::Left,
+                Direction::Right,
+            ] {
+                let next = self.wrap(cell.step(direction));
+                let index = self.index(next);
+                if !reached[index] && self.get(next) == Tile::Free {
+                    reached[index] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        reached
+    }
+
+    /// Clears an L-shaped path between two cells.
+    fn carve_path(&mut self, from: Cell, to: Cell) {
+        let step_x = (to.x - from.x).signum();
+        let step_y = (to.y - from.y).signum();
+
+        let mut cell = from;
+        while cell.x != to.x {
+            self.set(cell, Tile::Free);
+            cell.x += step_x;
+        }
+        while cell.y != to.y {
+            self.set(cell, Tile::Free);
+            cell.y += step_y;
+        }
+        self.set(cell, Tile::Free);
+    }
+
+    /// Makes sure every spawn point can reach the others, then fills in all
+    /// free cells that are still cut off.
+    fn connect(&mut self, spawns: &[SpawnPoint]) {
+        for spawn in spawns {
+            let head = self.wrap(spawn.cell.step(spawn.direction));
+            self.set(spawn.cell, Tile::Free);
+            self.set(head, Tile::Free);
+        }
+
+        let origin = spawns[0].cell;
+        for spawn in &spawns[1..] {
+            if !self.flood(origin)[self.index(spawn.cell)] {
+                self.carve_path(origin, spawn.cell);
+            }
+        }
+
+        let reached = self.flood(origin);
+        for (index, tile) in self.tiles.iter_mut().enumerate() {
+            if *tile == Tile::Free && !reached[index] {
+                *tile = Tile::Obstacle;
+            }
+        }
+    }
+
+    fn into_map(self, name: String, spawns: Vec<SpawnPoint>) -> Map {
+        let mut walls = Vec::new();
+        let mut obstacles = Vec::new();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = Cell::new(x, y);
+                match self.get(cell) {
+                    Tile::Free => {}
+                    Tile::Wall => walls.push(cell),
+                    Tile::Obstacle => obstacles.push(cell),
+                }
+            }
+        }
+
+        Map {
+            name,
+            width: self.width,
+            height: self.height,
+            walls,
+            obstacles,
+            spawns,
+            fruit_zones: Vec::new(),
+        }
+    }
+}
+
+fn near_any(cell: Cell, spawns: &[Cell]) -> bool {
+    spawns.iter().any(|spawn| {
+        (spawn.x - cell.x).abs() <= SPAWN_CLEARANCE && (spawn.y - cell.y).abs() <= SPAWN_CLEARANCE
+    })
+}
+
+/// Small blocks mirrored through the centre of the board, like the pairs
+/// of default spawns, so both players of a pair face the same surroundings.
+fn scattered_blocks(rng: &mut StdRng, width: i32, height: i32) -> (Layout, Vec<SpawnPoint>) {
+    let mut layout = Layout::new(width, height, Tile::Free);
+    layout.add_border();
+
+    let spawns = default_spawns(width, height);
+    let mirror = |cell: Cell| Cell::new(width - 1 - cell.x, height - 1 - cell.y);
+    let reserved: Vec<Cell> = spawns.iter().map(|spawn| spawn.cell).collect();
+
+    for _ in 0..width * height / CELLS_PER_BLOCK_PAIR {
+        let block_width = rng.random_range(1..=BLOCK_SIZE_MAX);
+        let block_height = rng.random_range(1..=BLOCK_SIZE_MAX);
+        let x = rng.random_range(1..width - 1 - block_width);
+        let y = rng.random_range(1..height - 1 - block_height);
+
+        for cell_y in y..y + block_height {
+            for cell_x in x..x + block_width {
+                let cell = Cell::new(cell_x, cell_y);
+                if !near_any(cell, &reserved) {
+                    layout.set(cell, Tile::Obstacle);
+                    layout.set(mirror(cell), Tile::Obstacle);
+                }
+            }
+        }
+    }
+
+    (layout, spawns)
+}
+
+fn zone_center(zone: &Zone) -> Cell {
+    Cell::new(zone.x + zone.width / 2, zone.y + zone.height / 2)
+}
+
+fn zones_overlap(a: &Zone, b: &Zone) -> bool {
+    // keep at least one solid cell between rooms
+    a.x <= b.x + b.width && b.x <= a.x + a.width && a.y <= b.y + b.height && b.y <= a.y + a.height
+}
+
+/// Rectangular rooms cut out of solid rock, joined left to right by
+/// corridors two cells wide.
+fn rooms_and_corridors(rng: &mut StdRng, width: i32, height: i32) -> (Layout, Vec<SpawnPoint>) {
+    let mut layout = Layout::new(width, height, Tile::Obstacle);
+    layout.add_border();
+
+    let mut rooms: Vec<Zone> = Vec::new();
+    for _ in 0..ROOM_ATTEMPTS {
+        if rooms.len() == ROOM_COUNT {
+            break;
+        }
+
+        let room_width = rng.random_range(5..=9);
+        let room_height = rng.random_range(4..=7);
+        let room = Zone {
+            x: rng.random_range(2..width - 2 - room_width),
+            y: rng.random_range(2..height - 2 - room_height),
+            width: room_width,
+            height: room_height,
+        };
+
+        if !rooms.iter().any(|other| zones_overlap(&room, other)) {
+            rooms.push(room);
+        }
+    }
+
+    if rooms.len() < PLAYER_COUNT_MAX {
+        rooms = default_spawns(width, height)
+            .iter()
+            .map(|spawn| Zone {
+                x: spawn.cell.x - 2,
+                y: spawn.cell.y - 1,
+                width: 5,
+                height: 3,
+            })
+            .collect();
+    }
+
+    rooms.sort_by_key(|room| zone_center(room).x);
+    for room in &rooms {
+        layout.fill(room, Tile::Free);
+    }
+
+    for pair in rooms.windows(2) {
+        let from = zone_center(&pair[0]);
+        let to = zone_center(&pair[1]);
+        for offset in 0..2 {
+            layout.carve_path(
+                Cell::new(from.x, from.y + offset),
+                Cell::new(to.x + offset, to.y),
+            );
+        }
+    }
+
+    let spawns = (0..PLAYER_COUNT_MAX)
+        .map(|index| {
+            let room_index = if PLAYER_COUNT_MAX > 1 {
+                index * (rooms.len() - 1) / (PLAYER_COUNT_MAX - 1)
+            } else {
+                0
+            };
+            let cell = zone_center(&rooms[room_index]);
+            let direction = if cell.x < width / 2 {
+                Direction::Right
+            } else {
+                Direction::Left
+            };
+            SpawnPoint { cell, direction }
+        })
+        .collect();
+
+    (layout, spawns)
+}
+
+/// Perfect maze carved with a randomised depth-first search, with paths
+/// widened to two cells so snakes can turn around in them.
+fn maze(rng: &mut StdRng, width: i32, height: i32) -> (Layout, Vec<SpawnPoint>) {
+    let mut layout = Layout::new(width, height, Tile::Wall);
+
+    let columns = (width - 1) / MAZE_PITCH;
+    let rows = (height - 1) / MAZE_PITCH;
+    let block = |column: i32, row: i32| Zone {
+        x: 1 + column * MAZE_PITCH,
+        y: 1 + row * MAZE_PITCH,
+        width: MAZE_PATH_WIDTH,
+        height: MAZE_PATH_WIDTH,
+    };
+
+    let mut visited = vec![false; (columns * rows) as usize];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    layout.fill(&block(0, 0), Tile::Free);
+
+    while let Some(&(column, row)) = stack.last() {
+        let neighbours: Vec<(i32, i32)> = [(0, -1), (0, 1), (-1, 0), (1, 0)]
+            .iter()
+            .map(|(dx, dy)| (column + dx, row + dy))
+            .filter(|&(x, y)| {
+                x >= 0 && x < columns && y >= 0 && y < rows && !visited[(y * columns + x) as usize]
+            })
+            .collect();
+
+        if neighbours.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        let (next_column, next_row) = neighbours[rng.random_range(0..neighbours.len())];
+        visited[(next_row * columns + next_column) as usize] = true;
+
+        // carving the bounding box of both blocks also opens the wall between them
+        let from = block(column, row);
+        let to = block(next_column, next_row);
+        let x = from.x.min(to.x);
+        let y = from.y.min(to.y);
+        layout.fill(
+            &Zone {
+                x,
+                y,
+                width: from.x.max(to.x) + MAZE_PATH_WIDTH - x,
+                height: from.y.max(to.y) + MAZE_PATH_WIDTH - y,
+            },
+            Tile::Free,
+        );
+
+        stack.push((next_column, next_row));
+    }
+
+    let spawns = (0..PLAYER_COUNT_MAX)
+        .map(|index| {
+            let column = if PLAYER_COUNT_MAX > 1 {
+                index as i32 * (columns - 1) / (PLAYER_COUNT_MAX as i32 - 1)
+            } else {
+                0
+            };
+            let zone = block(column, rows / 2);
+            let cell = Cell::new(zone.x, zone.y);
+            SpawnPoint {
+                cell,
+                direction: open_direction(&layout, cell),
+            }
+        })
+        .collect();
+
+    (layout, spawns)
+}
+
+/// Direction in which the head and the cell after it are both free, so a
+/// snake spawned at `cell` starts along a passage instead of into a wall.
+fn open_direction(layout: &Layout, cell: Cell) -> Direction {
+    [
+        Direction::Down,
+        Direction::Right,
+        Direction::Up,
+        Direction::Left,
+    ]
+    .into_iter()
+    .find(|&direction| {
+        let head = layout.wrap(cell.step(direction));
+        layout.get(head) == Tile::Free
+            && layout.get(layout.wrap(head.step(direction))) == Tile::Free
+    })
+    .unwrap_or(Direction::Down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snake_cfg::{BOARD_HEIGHT, BOARD_WIDTH};
+
+    /// Free cells of `map` reachable from `origin`, wrapping at the edges.
+    fn reachable(map: &Map, origin: Cell) -> Vec<bool> {
+        let index = |cell: Cell| (cell.y * map.width + cell.x) as usize;
+        let mut blocked = vec![false; (map.width * map.height) as usize];
+        for cell in map.walls.iter().chain(&map.obstacles) {
+            blocked[index(*cell)] = true;
+        }
+
+        let mut reached = vec![false; blocked.len()];
+        let mut queue = VecDeque::from([origin]);
+        reached[index(origin)] = true;
+        while let Some(cell) = queue.pop_front() {
+            for direction in [
+                Direction::Up,
+                Direction::Down,
+                Direction::Left,
+                Direction::Right,
+            ] {
+                let next = map.wrap(cell.step(direction));
+                if !blocked[index(next)] && !reached[index(next)] {
+                    reached[index(next)] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        for (reached, blocked) in reached.iter_mut().zip(&blocked) {
+            *reached |= *blocked;
+        }
+        reached
+    }
+
+    #[test]
+    fn every_free_cell_is_reachable_from_the_spawns() {
+        for generator in [
+            ArenaGenerator::ScatteredBlocks,
+            ArenaGenerator::RoomsAndCorridors,
+            ArenaGenerator::Maze,
+        ] {
+            for seed in 0..20 {
+                let map = generator.generate(seed, BOARD_WIDTH, BOARD_HEIGHT);
+                assert_eq!(map.spawns.len(), PLAYER_COUNT_MAX);
+
+                let reached = reachable(&map, map.spawns[0].cell);
+                assert!(
+                    reached.iter().all(|&reached| reached),
+                    "{} has cut off cells",
+                    map.name
+                );
+                for spawn in &map.spawns {
+                    let head = map.wrap(spawn.cell.step(spawn.direction));
+                    assert!(
+                        !map.is_blocked(&spawn.cell) && !map.is_blocked(&head),
+                        "{} has a blocked spawn at {:?}",
+                        map.name,
+                        spawn.cell
+                    );
+                }
+            }
+        }
+    }
+}
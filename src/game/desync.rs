@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::game_core::GameCore;
+
+/// Predictions made since the last server state; a few ticks' worth is
+/// plenty.
+const PREDICTIONS_KEPT: usize = 8;
+
+#[derive(Debug)]
+struct Prediction {
+    match_tick: u64,
+    hash: u64,
+    /// Kept only when divergences are dumped.
+    state: Option<GameCore>,
+}
+
+/// Compares the states the client predicts with the hashes the server sends
+/// for the same ticks, counting the ticks they disagree on.
+#[derive(Debug)]
+pub struct DesyncCheck {
+    predictions: VecDeque<Prediction>,
+    pub checked: u64,
+    pub divergences: u64,
+    pub last_divergence_tick: Option<u64>,
+    /// Where both states of a divergence are written, if anywhere.
+    dump_dir: Option<PathBuf>,
+    /// Predicted state of the last divergence, until the server's arrives.
+    pending_dump: Option<GameCore>,
+}
+
+impl DesyncCheck {
+    pub fn new(dump_dir: Option<PathBuf>) -> Self {
+        Self {
+            predictions: VecDeque::new(),
+            checked: 0,
+            divergences: 0,
+            last_divergence_tick: None,
+            dump_dir,
+            pending_dump: None,
+        }
+    }
+
+    pub fn record_prediction(&mut self, game: &GameCore) {
+        if self.predictions.len() == PREDICTIONS_KEPT {
+            self.predictions.pop_front();
+        }
+        self.predictions.push_back(Prediction {
+            match_tick: game.match_tick,
+            hash: game.state_hash(),
+            state: self.dump_dir.as_ref().map(|_| game.clone()),
+        });
+    }
+
+    /// Checks the server's hash against the prediction for the same tick,
+    /// if there is one.
+    pub fn check(&mut self, match_tick: u64, hash: u64) {
+        let Some(prediction) = self
+            .predictions
+            .iter_mut()
+            .find(|prediction| prediction.match_tick == match_tick)
+        else {
+            return;
+        };
+
+        self.checked += 1;
+        if prediction.hash == hash {
+            return;
+        }
+
+        self.divergences += 1;
+        self.last_divergence_tick = Some(match_tick);
+        eprintln!(
+            "[WARNING]: desync at tick {}: predicted {:016x}, server {:016x}",
+            match_tick, prediction.hash, hash
+        );
+        self.pending_dump = prediction.state.take();
+    }
+
+    /// Called with every state from the server. Predictions made before it
+    /// are stale from then on.
+    pub fn on_server_state(&mut self, game: &GameCore) {
+        self.predictions.clear();
+        if let Some(predicted) = self.pending_dump.take()
+            && predicted.match_tick == game.match_tick
+        {
+            self.dump(&predicted, game);
+        }
+    }
+
+    fn dump(&self, predicted: &GameCore, server: &GameCore) {
+        let Some(dir) = &self.dump_dir else {
+            return;
+        };
+        if let Err(err) = fs::create_dir_all(dir) {
+            eprintln!("[ERROR] failed to create {}: {}", dir.display(), err);
+            return;
+        }
+
+        for (name, state) in [("predicted", predicted), ("server", server)] {
+            let path = dir.join(format!("desync_{}_{}.json", server.match_tick, name));
+            let result = serde_json::to_string_pretty(state)
+                .map_err(|err| err.to_string())
+                .and_then(|json| fs::write(&path, json).map_err(|err| err.to_string()));
+            if let Err(err) = result {
+                eprintln!("[ERROR] failed to write {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    pub fn get_overlay_text(&self) -> String {
+        let last = match self.last_divergence_tick {
+            Some(tick) => format!(", last at tick {}", tick),
+            None => String::new(),
+        };
+        format!(
+            "desync: {} of {} ticks checked{}",
+            self.divergences, self.checked, last
+        )
+    }
+}
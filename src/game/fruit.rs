@@ -0,0 +1,135 @@
+use macroquad::{color::Color, prelude as mcq};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::common::MyVec2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FruitKind {
+    Normal,
+    Golden,
+    Poison,
+    Speed,
+    Slow,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FruitEffect {
+    Grow(u32),
+    /// Removes segments; the snake dies if it would end up shorter than one.
+    Shrink(u32),
+    /// Temporarily adds to the ticks a snake waits between steps, so a
+    /// negative delta speeds it up.
+    ChangeSpeed {
+        ticks_per_move_delta: i32,
+        duration_secs: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct FruitType {
+    pub kind: FruitKind,
+    pub effect: FruitEffect,
+    /// Relative chance of this type being picked when a fruit spawns.
+    pub spawn_weight: u32,
+    /// Seconds after which an uneaten fruit disappears, `None` if it stays.
+    pub lifetime_secs: Option<u64>,
+    /// Points the eater gets.
+    pub score: u32,
+    pub color: Color,
+}
+
+pub const FRUIT_CATALOGUE: [FruitType; 5] = [
+    FruitType {
+        kind: FruitKind::Normal,
+        effect: FruitEffect::Grow(1),
+        spawn_weight: 60,
+        lifetime_secs: None,
+        score: 10,
+        color: mcq::YELLOW,
+    },
+    FruitType {
+        kind: FruitKind::Golden,
+        effect: FruitEffect::Grow(3),
+        spawn_weight: 10,
+        lifetime_secs: Some(5),
+        score: 30,
+        color: mcq::GOLD,
+    },
+    FruitType {
+        kind: FruitKind::Poison,
+        effect: FruitEffect::Shrink(2),
+        spawn_weight: 10,
+        lifetime_secs: None,
+        score: 0,
+        color: mcq::PURPLE,
+    },
+    FruitType {
+        kind: FruitKind::Speed,
+        effect: FruitEffect::ChangeSpeed {
+            ticks_per_move_delta: -8,
+            duration_secs: 5,
+        },
+        spawn_weight: 10,
+        lifetime_secs: None,
+        score: 10,
+        color: mcq::SKYBLUE,
+    },
+    FruitType {
+        kind: FruitKind::Slow,
+        effect: FruitEffect::ChangeSpeed {
+            ticks_per_move_delta: 10,
+            duration_secs: 5,
+        },
+        spawn_weight: 10,
+        lifetime_secs: None,
+        score: 10,
+        color: mcq::WHITE,
+    },
+];
+
+impl FruitKind {
+    pub fn info(&self) -> &'static FruitType {
+        FRUIT_CATALOGUE
+            .iter()
+            .find(|fruit_type| fruit_type.kind == *self)
+            .expect("every fruit kind is in the catalogue")
+    }
+
+    /// Picks a fruit kind at random, according to the catalogue spawn weights.
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let total_weight: u32 = FRUIT_CATALOGUE.iter().map(|t| t.spawn_weight).sum();
+        let mut picked = rng.random_range(0..total_weight);
+        for fruit_type in &FRUIT_CATALOGUE {
+            if picked < fruit_type.spawn_weight {
+                return fruit_type.kind;
+            }
+            picked -= fruit_type.spawn_weight;
+        }
+        unreachable!("picked weight is within total catalogue weight");
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fruit {
+    pub kind: FruitKind,
+    pub pos: MyVec2,
+    pub expires_at: Option<u64>,
+}
+
+impl Fruit {
+    pub fn new(kind: FruitKind, pos: MyVec2, tick: u64, tick_rate: u64) -> Self {
+        Self {
+            kind,
+            pos,
+            expires_at: kind
+                .info()
+                .lifetime_secs
+                .map(|lifetime| tick + lifetime * tick_rate),
+        }
+    }
+
+    pub fn is_expired(&self, tick: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| tick >= expires_at)
+    }
+}
@@ -1,29 +1,117 @@
 use std::collections::BTreeMap;
 
 use crate::{
-    common::{MyVec2, from_color},
+    common::{MyColor, MyVec2, from_color},
     snake_cfg::*,
 };
 use macroquad::prelude as mcq;
-use rand::{Rng, rng};
+use rand::Rng;
 
-use crate::snake::{Direction, Snake};
+use crate::arena::ArenaGenerator;
+use crate::fruit::{Fruit, FruitEffect, FruitKind};
+use crate::game_event::GameEvent;
+use crate::game_mode::GameModeKind;
+use crate::game_rng::GameRng;
+use crate::map::{Cell, Map, Tile};
+use crate::occupancy::Occupancy;
+use crate::safe_zone::SafeZone;
+use crate::snake::{Direction, Snake, TailRule};
 
 use serde::{Serialize, Deserialize};
 
 use super::snake::SnakesColission;
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DeathCause {
+    SelfColission,
+    /// Both heads met; neither player gets the kill.
+    HeadToHead,
+    /// Ran into another snake's body or tail.
+    OtherTail,
+    Wall,
+    Obstacle,
+    Poisoned,
+    /// Was still outside the safe zone when it shrank.
+    OutsideZone,
+    Disconnect,
+    Timeout,
+}
+
+impl DeathCause {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeathCause::SelfColission => "ran into itself",
+            DeathCause::HeadToHead => "crashed head-on",
+            DeathCause::OtherTail => "ran into another snake",
+            DeathCause::Wall => "hit a wall",
+            DeathCause::Obstacle => "hit an obstacle",
+            DeathCause::Poisoned => "was poisoned",
+            DeathCause::OutsideZone => "was caught outside the zone",
+            DeathCause::Disconnect => "disconnected",
+            DeathCause::Timeout => "ran out of time",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Death {
+    pub cause: DeathCause,
+    /// Player whose snake was run into, if any.
+    pub killer: Option<String>,
+    pub tick: u64,
+}
+
+impl Death {
+    pub fn description(&self) -> String {
+        match &self.killer {
+            Some(killer) => format!("{} ({})", self.cause.name(), killer),
+            None => self.cause.name().to_string(),
+        }
+    }
+}
+
+/// A player's final position. Players that tied share a place.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Standing {
+    pub place: usize,
+    pub name: String,
+    /// `None` if the player survived until the end.
+    pub death: Option<Death>,
+}
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FinishDetails {
-    pub draw: bool,
-    pub winner: String,
+    /// Ordered from first place to last.
+    pub standings: Vec<Standing>,
+    /// Whether this round decided the match.
+    pub match_over: bool,
+}
+
+impl FinishDetails {
+    /// Everyone in first place; more than one means a draw.
+    pub fn winners(&self) -> Vec<&str> {
+        self.standings
+            .iter()
+            .filter(|standing| standing.place == 1)
+            .map(|standing| standing.name.as_str())
+            .collect()
+    }
+
+    pub fn is_draw(&self) -> bool {
+        self.winners().len() != 1
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum GameState {
     NotStarted,
     Paused,
+    /// The round is set up and starts, or resumes, once this runs out.
+    Countdown {
+        ticks_left: u64,
+        /// Whether a paused round resumes, rather than a new one starting.
+        resuming: bool,
+    },
     Playing,
     Finished(FinishDetails),
 }
@@ -34,10 +122,186 @@ pub enum PlayerState {
     Ready,
 }
 
+/// What a player achieved in the current game.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerStats {
+    pub death: Option<Death>,
+    pub fruits_eaten: u32,
+    pub length: usize,
+    pub max_length: usize,
+    /// Snakes that died running into this player's snake.
+    pub kills: u32,
+    pub survival_ticks: u64,
+    pub score: u32,
+}
+
+impl PlayerStats {
+    pub fn alive(&self) -> bool {
+        self.death.is_none()
+    }
+
+    pub fn survival_seconds(&self, tick_rate: u64) -> u64 {
+        self.survival_ticks / tick_rate
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Player {
     pub name: String,
     pub state: PlayerState,
+    pub stats: PlayerStats,
+}
+
+/// How snakes speed up as the game goes on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SpeedProgression {
+    Constant,
+    /// One tick faster for every `SNAKE_SPEEDUP_LENGTH_STEP` segments.
+    ByLength,
+    /// One tick faster every `SNAKE_SPEEDUP_TIME_STEP_SECS` seconds of play.
+    ByTime,
+}
+
+impl SpeedProgression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedProgression::Constant => "constant",
+            SpeedProgression::ByLength => "by length",
+            SpeedProgression::ByTime => "by time",
+        }
+    }
+}
+
+/// How many rounds make up a match.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MatchFormat {
+    /// Whoever wins the majority of at most this many rounds.
+    BestOf(u32),
+    /// Whoever wins this many rounds first, however many it takes.
+    FirstTo(u32),
+}
+
+impl MatchFormat {
+    fn wins_needed(&self) -> u32 {
+        match *self {
+            MatchFormat::BestOf(rounds) => rounds / 2 + 1,
+            MatchFormat::FirstTo(wins) => wins,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            MatchFormat::BestOf(1) => String::from("single round"),
+            MatchFormat::BestOf(rounds) => format!("best of {}", rounds),
+            MatchFormat::FirstTo(wins) => format!("first to {} wins", wins),
+        }
+    }
+}
+
+/// Progress of the match the rounds are played in. Kept across rounds and
+/// only reset when a new match starts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MatchState {
+    /// Number of the round being played or just finished, starting at 1.
+    pub round: u32,
+    pub round_wins: BTreeMap<String, u32>,
+}
+
+impl MatchState {
+    pub fn wins(&self, name: &str) -> u32 {
+        self.round_wins.get(name).copied().unwrap_or(0)
+    }
+
+    fn is_decided(&self, format: MatchFormat) -> bool {
+        let leader = self.round_wins.values().copied().max().unwrap_or(0);
+        match format {
+            MatchFormat::BestOf(rounds) => leader >= format.wins_needed() || self.round >= rounds,
+            MatchFormat::FirstTo(_) => leader >= format.wins_needed(),
+        }
+    }
+
+    /// Everyone with the most round wins; more than one means a drawn match.
+    pub fn leaders(&self) -> Vec<&str> {
+        let leader = self.round_wins.values().copied().max().unwrap_or(0);
+        self.round_wins
+            .iter()
+            .filter(|(_, wins)| **wins == leader)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// How survivors are ranked when a round ends with more than one of them,
+/// i.e. on a full board or when time runs out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Tiebreak {
+    Length,
+    Score,
+}
+
+impl Tiebreak {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tiebreak::Length => "longest snake wins",
+            Tiebreak::Score => "highest score wins",
+        }
+    }
+}
+
+/// Rules that can be tuned per lobby.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameSettings {
+    pub mode: GameModeKind,
+    /// Players a match is played with; it starts once that many are ready.
+    pub player_count: usize,
+    /// Ticks a snake waits between two steps at the start of a game.
+    pub snake_ticks_per_move: u32,
+    pub speed_progression: SpeedProgression,
+    /// Fruits kept on the board regardless of the number of players.
+    pub fruit_count: usize,
+    /// Additional fruits kept on the board for every player in the game.
+    pub fruits_per_player: usize,
+    /// Minimum Manhattan distance between two fruits, in cells.
+    pub fruit_min_distance: i32,
+    pub tail_rule: TailRule,
+    pub match_format: MatchFormat,
+    /// Seconds counted down before a round starts or resumes.
+    pub countdown_secs: u64,
+    /// Ticks of play after which a round is decided by `tiebreak`.
+    pub time_limit_ticks: Option<u64>,
+    pub tiebreak: Tiebreak,
+    /// Game updates per second the server runs at.
+    pub tick_rate: u64,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            mode: GameModeKind::Classic,
+            player_count: PLAYER_COUNT_DEFAULT,
+            snake_ticks_per_move: SNAKE_TICKS_PER_MOVE as u32,
+            speed_progression: SpeedProgression::Constant,
+            fruit_count: FRUIT_COUNT,
+            fruits_per_player: FRUITS_PER_PLAYER,
+            fruit_min_distance: FRUIT_MIN_DISTANCE,
+            tail_rule: TailRule::Vacated,
+            match_format: MatchFormat::BestOf(1),
+            countdown_secs: COUNTDOWN_SECS,
+            time_limit_ticks: None,
+            tiebreak: Tiebreak::Length,
+            tick_rate: TICK_RATE_FREQ,
+        }
+    }
+}
+
+/// Where the board for the next game comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ArenaSource {
+    Loaded,
+    Generated {
+        generator: ArenaGenerator,
+        seed: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -45,75 +309,390 @@ pub struct GameCore {
     pub state: GameState,
     pub players: BTreeMap<String, Player>,
     snakes: BTreeMap<String, Snake>,
-    pub fruit_pos: Option<MyVec2>,
+    pub fruits: Vec<Fruit>,
+    pub tick: u64,
+    /// Updates since the current match started, lobby time between rounds
+    /// included. Replays tag inputs with it.
+    pub match_tick: u64,
+    pub settings: GameSettings,
+    pub arena: ArenaSource,
+    /// Seed the current match's random numbers started from.
+    pub seed: u64,
+    rng: GameRng,
+    pub match_state: MatchState,
+    /// Ticks of play left in a timed round.
+    pub time_left: Option<u64>,
+    /// Set when time ran out on a tie; the next collision ends the round.
+    pub sudden_death: bool,
+    pub safe_zone: Option<SafeZone>,
+    map: Map,
+    #[serde(skip)]
+    loaded_map: Map,
+    #[serde(skip)]
+    occupancy: Occupancy,
+    /// Events since the last `drain_events`.
+    #[serde(skip)]
+    events: Vec<GameEvent>,
     is_server: bool,
 }
 
-const ENTER: char = '\x0D';
-const ESCAPE: char = '\x1B';
+pub const ENTER: char = '\x0D';
+pub const ESCAPE: char = '\x1B';
 
-fn generate_fruit_pos() -> MyVec2 {
-    let mut pos = MyVec2::new(0.0, 0.0);
+// short enough to read off the lobby screen
+const ARENA_SEED_MAX: u64 = 1_000_000;
 
-    let n_snake_fits_x: u32 = (SCREEN_WIDTH / SNAKE_SIZE) as u32;
-    let n_snake_fits_y: u32 = (SCREEN_HEIGHT / SNAKE_SIZE) as u32;
-
-    pos.x = SNAKE_SIZE * rng().random_range(0..n_snake_fits_x) as f32;
-    pos.y = SNAKE_SIZE * rng().random_range(0..n_snake_fits_y) as f32;
-
-    pos
-}
+const SPEED_SETTING_STEP: u32 = 2;
 
-fn align_to_snake_size(pos: f32) -> u32 {
-    let pos = pos as u32;
-    let remainder = pos % SNAKE_SIZE as u32;
-    pos - remainder
-}
+const TIME_LIMIT_STEP_SECS: u64 = 60;
+const TIME_LIMIT_MAX_STEPS: u64 = 5;
 
 pub enum PlayerColission {
     SelfColission(String),
+    WallColission(String),
+    ObstacleColission(String),
+    /// Colission of the first snake's head with the second snake.
     InBetween(SnakesColission, String, String),
-    FruitColission(String),
+}
+
+/// FNV-1a, fed the serialized game state.
+struct StateHasher(u64);
+
+impl Extend<u8> for StateHasher {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, bytes: I) {
+        for byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01B3);
+        }
+    }
 }
 
 impl GameCore {
     pub fn new(is_server: bool) -> Self {
+        Self::with_seed(is_server, rand::random())
+    }
+
+    pub fn with_seed(is_server: bool, seed: u64) -> Self {
         Self {
             state: GameState::NotStarted,
             snakes: BTreeMap::new(),
             players: BTreeMap::new(),
-            fruit_pos: None,
+            fruits: Vec::new(),
+            tick: 0,
+            match_tick: 0,
+            settings: GameSettings::default(),
+            arena: ArenaSource::Loaded,
+            seed,
+            rng: GameRng::new(seed),
+            match_state: MatchState::default(),
+            time_left: None,
+            sudden_death: false,
+            safe_zone: None,
+            map: Map::default(),
+            loaded_map: Map::default(),
+            occupancy: Occupancy::new(&Map::default(), PLAYER_COUNT_MAX),
+            events: Vec::new(),
             is_server,
         }
     }
 
+    pub fn set_map(&mut self, map: Map) {
+        self.reset_game_state();
+        self.loaded_map = map;
+        if let ArenaSource::Loaded = self.arena {
+            self.map = self.loaded_map.clone();
+            self.rebuild_occupancy();
+        }
+    }
+
+    /// Compact fingerprint of everything sent to clients, to tell whether
+    /// two games are in step.
+    pub fn state_hash(&self) -> u64 {
+        postcard::to_extend(self, StateHasher(0xCBF2_9CE4_8422_2325)).map_or(0, |hasher| hasher.0)
+    }
+
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    /// Brings back a game saved to disk. A round in play is paused until
+    /// its players are back and ready again; outside a match the lobby is
+    /// left open to anyone.
+    pub fn resume(&mut self, loaded_map: Map) {
+        self.loaded_map = loaded_map;
+        let in_match = match &self.state {
+            GameState::NotStarted => false,
+            GameState::Finished(details) => !details.match_over,
+            _ => true,
+        };
+
+        if in_match {
+            for player in self.players.values_mut() {
+                player.state = PlayerState::NotReady;
+            }
+        } else {
+            self.players.clear();
+        }
+        if let GameState::Countdown { .. } | GameState::Playing = self.state {
+            self.state = GameState::Paused;
+        }
+        self.rebuild_occupancy();
+    }
+
+    /// Color and segments of every snake on the board.
+    pub fn snake_bodies(&self) -> impl Iterator<Item = (MyColor, &[MyVec2])> {
+        self.snakes
+            .values()
+            .map(|snake| (snake.color(), snake.positions()))
+    }
+
+    /// Board used when the arena isn't generated.
+    pub fn loaded_map(&self) -> &Map {
+        &self.loaded_map
+    }
+
+    pub fn set_settings(&mut self, settings: GameSettings) {
+        self.settings = settings;
+    }
+
+    fn change_speed_setting(&mut self, faster: bool) {
+        let ticks_per_move = if faster {
+            self.settings.snake_ticks_per_move - SPEED_SETTING_STEP
+        } else {
+            self.settings.snake_ticks_per_move + SPEED_SETTING_STEP
+        };
+        self.settings.snake_ticks_per_move =
+            ticks_per_move.clamp(SNAKE_TICKS_PER_MOVE_MIN, SNAKE_TICKS_PER_MOVE_MAX);
+    }
+
+    fn select_next_speed_progression(&mut self) {
+        self.settings.speed_progression = match self.settings.speed_progression {
+            SpeedProgression::Constant => SpeedProgression::ByLength,
+            SpeedProgression::ByLength => SpeedProgression::ByTime,
+            SpeedProgression::ByTime => SpeedProgression::Constant,
+        };
+    }
+
+    pub fn speed_description(&self) -> String {
+        format!(
+            "{:.1} moves/s, {}",
+            self.settings.tick_rate as f32 / self.settings.snake_ticks_per_move as f32,
+            self.settings.speed_progression.name()
+        )
+    }
+
+    fn select_next_mode(&mut self) {
+        self.settings.mode = self.settings.mode.next();
+    }
+
+    /// Ticks in one step of the lobby's time limit setting.
+    fn time_limit_step(&self) -> u64 {
+        TIME_LIMIT_STEP_SECS * self.settings.tick_rate
+    }
+
+    fn select_next_time_limit(&mut self) {
+        let step = self.time_limit_step();
+        self.settings.time_limit_ticks = match self.settings.time_limit_ticks {
+            None => Some(step),
+            Some(ticks) if ticks >= TIME_LIMIT_MAX_STEPS * step => None,
+            Some(ticks) => Some(ticks + step),
+        };
+    }
+
+    fn select_next_tiebreak(&mut self) {
+        self.settings.tiebreak = match self.settings.tiebreak {
+            Tiebreak::Length => Tiebreak::Score,
+            Tiebreak::Score => Tiebreak::Length,
+        };
+    }
+
+    fn select_next_tail_rule(&mut self) {
+        self.settings.tail_rule = match self.settings.tail_rule {
+            TailRule::Vacated => TailRule::Blocked,
+            TailRule::Blocked => TailRule::Vacated,
+        };
+    }
+
+    pub fn time_limit_description(&self) -> String {
+        match self.settings.time_limit_ticks {
+            None => String::from("none"),
+            Some(ticks) => format!(
+                "{} min, {}",
+                ticks / self.time_limit_step(),
+                self.settings.tiebreak.name()
+            ),
+        }
+    }
+
+    fn select_next_match_format(&mut self) {
+        self.settings.match_format = match self.settings.match_format {
+            MatchFormat::BestOf(1) => MatchFormat::BestOf(3),
+            MatchFormat::BestOf(3) => MatchFormat::BestOf(5),
+            MatchFormat::BestOf(_) => MatchFormat::FirstTo(3),
+            MatchFormat::FirstTo(3) => MatchFormat::FirstTo(5),
+            MatchFormat::FirstTo(_) => MatchFormat::BestOf(1),
+        };
+    }
+
+    /// Whether the last round left the match undecided, so the next one
+    /// continues it.
+    fn is_match_in_progress(&self) -> bool {
+        matches!(&self.state, GameState::Finished(details) if !details.match_over)
+    }
+
+    pub fn set_arena(&mut self, arena: ArenaSource) {
+        self.arena = arena;
+        match self.arena {
+            ArenaSource::Loaded => self.map = self.loaded_map.clone(),
+            ArenaSource::Generated { .. } => self.generate_arena(),
+        }
+        self.rebuild_occupancy();
+    }
+
+    /// Rebuilds the board from the arena seed, if the arena is generated.
+    fn generate_arena(&mut self) {
+        if let ArenaSource::Generated { generator, seed } = self.arena {
+            self.map = generator.generate(seed, BOARD_WIDTH, BOARD_HEIGHT);
+        }
+    }
+
+    fn select_next_arena(&mut self) {
+        let next = match self.arena {
+            ArenaSource::Loaded => ArenaSource::Generated {
+                generator: ArenaGenerator::ScatteredBlocks,
+                seed: self.rng.random_range(0..ARENA_SEED_MAX),
+            },
+            ArenaSource::Generated { generator, seed } => match generator {
+                ArenaGenerator::ScatteredBlocks => ArenaSource::Generated {
+                    generator: ArenaGenerator::RoomsAndCorridors,
+                    seed,
+                },
+                ArenaGenerator::RoomsAndCorridors => ArenaSource::Generated {
+                    generator: ArenaGenerator::Maze,
+                    seed,
+                },
+                ArenaGenerator::Maze => ArenaSource::Loaded,
+            },
+        };
+        self.set_arena(next);
+    }
+
+    fn reroll_arena_seed(&mut self) {
+        if let ArenaSource::Generated { generator, .. } = self.arena {
+            let seed = self.rng.random_range(0..ARENA_SEED_MAX);
+            self.set_arena(ArenaSource::Generated { generator, seed });
+        }
+    }
+
+    pub fn arena_description(&self) -> String {
+        match self.arena {
+            ArenaSource::Loaded => format!("map '{}'", self.map.name),
+            ArenaSource::Generated { generator, seed } => {
+                format!("{} (seed {})", generator.name(), seed)
+            }
+        }
+    }
+
+    /// Whether no match is being played, not even between its rounds.
+    pub fn is_between_matches(&self) -> bool {
+        match &self.state {
+            GameState::NotStarted => true,
+            GameState::Finished(details) => details.match_over,
+            _ => false,
+        }
+    }
+
+    fn is_in_lobby(&self) -> bool {
+        matches!(self.state, GameState::NotStarted | GameState::Finished(_))
+    }
+
+    /// Starts the next round of the match in progress, or round one of a new
+    /// match.
     pub fn start(&mut self) {
+        if self.is_match_in_progress() {
+            self.match_state.round += 1;
+            self.start_round();
+        } else {
+            let seed = self.rng.random();
+            self.start_match(seed);
+        }
+    }
+
+    /// Starts round one of a new match. Every match draws its random numbers
+    /// from a seed of its own, so it can be replayed from that seed alone.
+    pub fn start_match(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = GameRng::new(seed);
+        self.match_tick = 0;
+        self.match_state = MatchState {
+            round: 1,
+            round_wins: BTreeMap::new(),
+        };
+        self.start_round();
+    }
+
+    fn start_round(&mut self) {
         self.snakes.clear();
+        self.fruits.clear();
+        self.tick = 0;
+        self.time_left = self.settings.time_limit_ticks;
+        self.sudden_death = false;
+        self.generate_arena();
+        self.safe_zone = None;
 
-        for (index, (name, _player)) in self.players.iter().enumerate() {
-            self.snakes.insert(
-                name.to_string(),
-                Snake::new(
-                    from_color(PLAYER_COLORS[index]),
-                    Self::create_player_position(index),
-                ),
+        let mode = self.settings.mode.rules();
+        let spawns = mode.spawn_layout(&self.map);
+        for (index, (name, player)) in self.players.iter_mut().enumerate() {
+            let spawn = &spawns[index];
+            let snake = Snake::new(
+                index,
+                from_color(PLAYER_COLORS[index]),
+                spawn.cell.to_pos(),
+                spawn.direction,
+                self.settings.snake_ticks_per_move,
+                &self.map,
             );
+            player.stats = PlayerStats {
+                length: snake.len(),
+                max_length: snake.len(),
+                ..PlayerStats::default()
+            };
+            self.snakes.insert(name.to_string(), snake);
         }
+
+        mode.on_round_start(self);
+        self.rebuild_occupancy();
+        self.events.push(GameEvent::RoundStarted {
+            round: self.match_state.round,
+        });
     }
 
-    fn create_player_position(player_index: usize) -> MyVec2 {
-        let x = {
-            let mut block = SCREEN_WIDTH / (PLAYER_COUNT_MAX + 1) as f32;
-            block = block * (player_index + 1) as f32;
-            align_to_snake_size(block) as f32
-        };
+    /// Recreates the occupancy grid from scratch. Needed whenever the board
+    /// changes, and after a game update arrives without one.
+    pub fn rebuild_occupancy(&mut self) {
+        let mut occupancy = Occupancy::new(&self.map, PLAYER_COUNT_MAX);
 
-        let y = {
-            let block = SCREEN_HEIGHT / 2.0;
-            align_to_snake_size(block) as f32
-        };
+        for snake in self.snakes.values() {
+            for pos in snake.positions() {
+                occupancy.add_segment(&Cell::from_pos(pos), snake.slot());
+            }
+        }
+        for fruit in &self.fruits {
+            occupancy.set_fruit(&Cell::from_pos(&fruit.pos), true);
+        }
+        if let Some(safe_zone) = &self.safe_zone {
+            for cell in safe_zone.closed_cells(&self.map) {
+                if occupancy.tile(&cell) == Tile::Free {
+                    occupancy.set_tile(&cell, Tile::Wall);
+                }
+            }
+        }
 
-        MyVec2 { x, y }
+        self.occupancy = occupancy;
     }
 
     pub fn add_player(&mut self, name: &str) {
@@ -122,234 +701,909 @@ impl GameCore {
             Player {
                 name: name.to_string(),
                 state: PlayerState::NotReady,
+                stats: PlayerStats::default(),
             },
         );
     }
 
     pub fn reset_game_state(&mut self) {
         self.state = GameState::NotStarted;
-        self.fruit_pos = None;
+        self.fruits.clear();
         self.snakes.clear();
+        self.safe_zone = None;
+        self.rebuild_occupancy();
     }
 
     pub fn remove_player(&mut self, name: &str) {
-        self.reset_game_state();
+        if let GameState::Countdown { .. } | GameState::Playing | GameState::Paused = self.state {
+            self.disconnect_player(name);
+        }
+        // the match can't go on without them
+        if let GameState::Finished(details) = &mut self.state {
+            details.match_over = true;
+        }
         self.players.remove(name);
     }
 
+    /// A player leaving mid-game loses, which may leave a single survivor.
+    fn disconnect_player(&mut self, name: &str) {
+        if self
+            .players
+            .get(name)
+            .is_some_and(|player| player.stats.alive())
+        {
+            let death = Death {
+                cause: DeathCause::Disconnect,
+                killer: None,
+                tick: self.tick,
+            };
+            self.record_death(name, death);
+        }
+        self.remove_snake(name);
+
+        let alive = self
+            .players
+            .values()
+            .filter(|player| player.stats.alive())
+            .count();
+        if alive <= 1 {
+            self.finish_the_game();
+        }
+    }
+
+    fn record_death(&mut self, name: &str, death: Death) {
+        if let Some(player) = self.players.get_mut(name) {
+            player.stats.death = Some(death.clone());
+        }
+        self.events.push(GameEvent::SnakeDied {
+            player: name.to_string(),
+            death,
+        });
+    }
+
+    fn remove_snake(&mut self, name: &str) {
+        if let Some(snake) = self.snakes.remove(name) {
+            for pos in snake.positions() {
+                self.occupancy
+                    .remove_segment(&Cell::from_pos(pos), snake.slot());
+            }
+        }
+    }
+
+    fn fruit_target_count(&self) -> usize {
+        self.settings.fruit_count + self.settings.fruits_per_player * self.snakes.len()
+    }
+
+    /// Whether fruit may spawn in a cell that is already known to be free.
+    fn is_valid_fruit_cell(&self, cell: &Cell) -> bool {
+        let min_distance = self.settings.fruit_min_distance;
+
+        self.map.in_fruit_zone(cell)
+            && self
+                .safe_zone
+                .as_ref()
+                .is_none_or(|safe_zone| safe_zone.current.contains(cell))
+            && !self.fruits.iter().any(|fruit| {
+                let other = Cell::from_pos(&fruit.pos);
+                (other.x - cell.x).abs() + (other.y - cell.y).abs() < min_distance
+            })
+    }
+
+    /// Samples free cells until one is fit for fruit. On a crowded board,
+    /// where sampling keeps missing, falls back to picking among all of them.
+    fn find_fruit_cell(&mut self) -> Option<Cell> {
+        for _ in 0..FRUIT_SPAWN_ATTEMPTS {
+            let cell = self.occupancy.random_free_cell(&mut self.rng)?;
+            if self.is_valid_fruit_cell(&cell) {
+                return Some(cell);
+            }
+        }
+
+        let candidates: Vec<Cell> = self
+            .occupancy
+            .free_cells()
+            .filter(|cell| self.is_valid_fruit_cell(cell))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[self.rng.random_range(0..candidates.len())])
+    }
+
+    /// Drops expired fruit and, on the server, tops the board up to the
+    /// configured fruit count. Fruit that can't be placed this tick is
+    /// retried on the next one.
     pub fn update_fruit_pos(&mut self) {
-        let mut new_fruit_pos;
-
-        loop {
-            new_fruit_pos = generate_fruit_pos();
-            let mut fruit_collides = false;
-            for (_, (_, snake)) in self.snakes.iter().enumerate() {
-                if snake.collides_object(&new_fruit_pos) {
-                    fruit_collides = true;
+        let tick = self.tick;
+        for fruit in self.fruits.iter().filter(|fruit| fruit.is_expired(tick)) {
+            self.occupancy.set_fruit(&Cell::from_pos(&fruit.pos), false);
+        }
+        self.fruits.retain(|fruit| !fruit.is_expired(tick));
+
+        if !self.is_server {
+            return;
+        }
+
+        while self.fruits.len() < self.fruit_target_count() {
+            let Some(new_fruit_cell) = self.find_fruit_cell() else {
+                break;
+            };
+
+            let kind = FruitKind::random(&mut self.rng);
+            let fruit = Fruit::new(
+                kind,
+                new_fruit_cell.to_pos(),
+                self.tick,
+                self.settings.tick_rate,
+            );
+            self.occupancy.set_fruit(&new_fruit_cell, true);
+            self.events.push(GameEvent::FruitSpawned {
+                kind: fruit.kind,
+                pos: fruit.pos,
+            });
+            self.fruits.push(fruit);
+        }
+    }
+
+    /// Collisions caused by this tick's steps. Only snakes that actually
+    /// moved can run into something, so every collision is reported once.
+    pub fn get_colissions(&self) -> Vec<PlayerColission> {
+        let mut colissions = Vec::new();
+
+        for (player_name, snake) in self.snakes.iter().filter(|(_, snake)| snake.moved()) {
+            let head = Cell::from_pos(&snake.get_head_pos());
+
+            match self.occupancy.tile(&head) {
+                Tile::Wall => colissions.push(PlayerColission::WallColission(player_name.clone())),
+                Tile::Obstacle => {
+                    colissions.push(PlayerColission::ObstacleColission(player_name.clone()))
                 }
+                Tile::Free => {}
             }
-            if !fruit_collides {
-                break;
+            if self.occupancy.segments(&head, snake.slot()) > 1 {
+                colissions.push(PlayerColission::SelfColission(player_name.clone()));
+            }
+
+            for (other_name, other_snake) in self.snakes.iter() {
+                if other_name == player_name {
+                    continue;
+                }
+
+                let other_in_head_cell = self.occupancy.segments(&head, other_snake.slot()) > 0;
+                if let Some(colission) =
+                    snake.collides_other(other_snake, other_in_head_cell, self.settings.tail_rule)
+                {
+                    colissions.push(PlayerColission::InBetween(
+                        colission,
+                        player_name.clone(),
+                        other_name.clone(),
+                    ));
+                }
             }
         }
 
-        self.fruit_pos = Some(new_fruit_pos);
+        colissions
     }
 
-    pub fn get_colissions(&self) -> Option<PlayerColission> {
-        for (player_name, snake) in self.snakes.iter() {
-            if snake.collides_self() {
-                return Some(PlayerColission::SelfColission(player_name.clone()));
+    /// Lets every snake that stepped onto a fruit eat it. Returns the players
+    /// that were killed by what they ate.
+    fn eat_fruits(&mut self) -> Vec<String> {
+        let mode = self.settings.mode.rules();
+        let mut poisoned = Vec::new();
+
+        for (player_name, snake) in self.snakes.iter_mut().filter(|(_, snake)| snake.moved()) {
+            let head_pos = snake.get_head_pos();
+            if !self.occupancy.has_fruit(&Cell::from_pos(&head_pos)) {
+                continue;
             }
 
-            if let Some(fruit_pos) = &self.fruit_pos {
-                if snake.collides_object(fruit_pos) {
-                    return Some(PlayerColission::FruitColission(player_name.clone()));
+            let fruit_index = self
+                .fruits
+                .iter()
+                .position(|fruit| fruit.pos == head_pos)
+                .expect("occupancy grid is in sync with fruit");
+            let fruit = self.fruits.remove(fruit_index);
+            self.occupancy.set_fruit(&Cell::from_pos(&head_pos), false);
+
+            if let Some(player) = self.players.get_mut(player_name) {
+                player.stats.fruits_eaten += 1;
+                player.stats.score += fruit.kind.info().score;
+            }
+            self.events.push(GameEvent::FruitEaten {
+                player: player_name.clone(),
+                kind: fruit.kind,
+            });
+
+            let effect = match mode.fruit_effect(&fruit) {
+                FruitEffect::Grow(segments) => FruitEffect::Grow(segments),
+                // every fruit is food once it comes down to who's longest
+                _ if self.sudden_death => FruitEffect::Grow(1),
+                effect => effect,
+            };
+            match effect {
+                FruitEffect::Grow(segments) => {
+                    let tail = Cell::from_pos(&snake.previous_tail_pos());
+                    snake.grow_by(segments);
+                    for _ in 0..segments {
+                        self.occupancy.add_segment(&tail, snake.slot());
+                    }
+                }
+                FruitEffect::Shrink(segments) => match snake.shrink(segments) {
+                    Some(removed) => {
+                        for pos in removed {
+                            self.occupancy
+                                .remove_segment(&Cell::from_pos(&pos), snake.slot());
+                        }
+                    }
+                    None => poisoned.push(player_name.clone()),
+                },
+                FruitEffect::ChangeSpeed {
+                    ticks_per_move_delta,
+                    duration_secs,
+                } => {
+                    let duration_ticks = duration_secs * self.settings.tick_rate as u32;
+                    snake.set_speed_effect(ticks_per_move_delta, duration_ticks);
+                    self.events.push(GameEvent::PowerUpActivated {
+                        player: player_name.clone(),
+                        kind: fruit.kind,
+                        duration_ticks,
+                    });
                 }
             }
         }
 
-        if self.snakes.iter().count() > 2 {
-            todo!("colissions for more than 2 player");
+        poisoned
+    }
+
+    /// What `settings.tiebreak` compares a player by.
+    fn tiebreak_value(&self, name: &str) -> u64 {
+        match self.settings.tiebreak {
+            Tiebreak::Length => self.snakes.get(name).map_or(0, Snake::len) as u64,
+            Tiebreak::Score => self
+                .players
+                .get(name)
+                .map_or(0, |player| player.stats.score as u64),
         }
+    }
 
-        let (player1_name, player1_snake) = self.snakes.iter().nth(0).unwrap();
-        let (player2_name, player2_snake) = self.snakes.iter().nth(1).unwrap();
+    /// Final positions of the round, as the mode ranks them.
+    fn standings(&self) -> Vec<Standing> {
+        self.settings.mode.rules().standings(self)
+    }
 
-        if let Some(colission) = player1_snake.collides_other(player2_snake) {
-            return Some(PlayerColission::InBetween(
-                colission,
-                player1_name.clone(),
-                player2_name.clone(),
-            ));
-        }
+    /// Ranks every player: survivors first, ordered by the tiebreak, then the
+    /// dead from the last to die to the first. Players dying on the same
+    /// tick share a place, unless they lost on time.
+    pub(crate) fn ranked_by_survival(&self) -> Vec<Standing> {
+        let mut ranked: Vec<((bool, u64, u64), &Player)> = self
+            .players
+            .values()
+            .map(|player| {
+                let key = match &player.stats.death {
+                    None => (true, 0, self.tiebreak_value(&player.name)),
+                    Some(death) if death.cause == DeathCause::Timeout => {
+                        (false, death.tick, self.tiebreak_value(&player.name))
+                    }
+                    Some(death) => (false, death.tick, 0),
+                };
+                (key, player)
+            })
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
 
-        if let Some(colission) = player2_snake.collides_other(player1_snake) {
-            return Some(PlayerColission::InBetween(
-                colission,
-                player2_name.clone(),
-                player1_name.clone(),
-            ));
+        let mut standings: Vec<Standing> = Vec::with_capacity(ranked.len());
+        for (index, (key, player)) in ranked.iter().enumerate() {
+            let place = match standings.last() {
+                Some(previous) if ranked[index - 1].0 == *key => previous.place,
+                _ => index + 1,
+            };
+            standings.push(Standing {
+                place,
+                name: player.name.clone(),
+                death: player.stats.death.clone(),
+            });
         }
-
-        None
+        standings
     }
 
-    pub fn finish_the_game(&mut self, winner: Option<&str>) {
-        for (_, player) in &mut self.players {
+    pub fn finish_the_game(&mut self) {
+        for player in self.players.values_mut() {
             player.state = PlayerState::NotReady;
         }
 
-        let details = FinishDetails {
-            draw: !winner.is_some(),
-            winner: winner.unwrap_or("").to_string(),
+        let mut details = FinishDetails {
+            standings: self.standings(),
+            match_over: false,
         };
+        for winner in self.settings.mode.rules().round_winners(&details) {
+            *self.match_state.round_wins.entry(winner).or_insert(0) += 1;
+        }
+        details.match_over = self.match_state.is_decided(self.settings.match_format);
+        self.events.push(GameEvent::GameFinished {
+            details: details.clone(),
+        });
         self.state = GameState::Finished(details);
     }
 
-    pub fn get_enemy_name(&mut self, player_name: &str) -> String {
-        let enemy_name = self
-            .players
-            .values()
-            .find(|iter_player| iter_player.name != player_name);
-        return enemy_name.unwrap().name.clone();
+    /// Resolves everything that happened during this tick's steps. Fruit is
+    /// eaten first, so a snake that grows keeps the tail cell it would
+    /// otherwise have vacated.
+    pub fn check_collissions(&mut self) {
+        let tick = self.tick;
+        // a snake can hit several things at once; the first one counts
+        let mut deaths: BTreeMap<String, Death> = BTreeMap::new();
+        let mut die = |name: String, cause: DeathCause, killer: Option<String>| {
+            deaths.entry(name).or_insert(Death {
+                cause,
+                killer,
+                tick,
+            });
+        };
+
+        for player_name in self.eat_fruits() {
+            die(player_name, DeathCause::Poisoned, None);
+        }
+
+        let mode = self.settings.mode.rules();
+        for colission in self.get_colissions() {
+            for (player_name, cause, killer) in mode.collision_outcome(colission) {
+                die(player_name, cause, killer);
+            }
+        }
+
+        self.resolve_deaths(deaths);
     }
 
-    pub fn check_collissions(&mut self) {
-        if let Some(player_colission) = self.get_colissions() {
-            match player_colission {
-                PlayerColission::FruitColission(player_name) => {
-                    if let Some(snake) = self.snakes.get_mut(player_name.as_str()) {
-                        snake.grow();
-                        self.fruit_pos = None;
-                    };
-                }
-                PlayerColission::SelfColission(player_name) => {
-                    let winner_name = self.get_enemy_name(&player_name);
-                    self.finish_the_game(Some(&winner_name));
-                }
-                PlayerColission::InBetween(snakes_colission, _loser, winner) => {
-                    match snakes_colission {
-                        SnakesColission::HeadToHeadColission => {
-                            self.finish_the_game(None);
-                        }
-                        SnakesColission::HeadToTailColission => {
-                            self.finish_the_game(Some(&winner));
-                        }
-                    }
-                }
+    /// Books the deaths of this tick and ends the round if they leave at
+    /// most one snake standing.
+    fn resolve_deaths(&mut self, deaths: BTreeMap<String, Death>) {
+        if deaths.is_empty() {
+            return;
+        }
+
+        for (loser, death) in &deaths {
+            if let Some(killer) = &death.killer
+                && let Some(player) = self.players.get_mut(killer)
+            {
+                player.stats.kills += 1;
+                player.stats.score += SCORE_PER_KILL;
+            }
+            self.record_death(loser, death.clone());
+        }
+
+        let survivors = self
+            .snakes
+            .keys()
+            .filter(|name| !deaths.contains_key(*name))
+            .count();
+
+        if self.settings.mode.rules().is_round_over(survivors) || self.sudden_death {
+            self.finish_the_game();
+        } else {
+            for loser in deaths.keys() {
+                self.remove_snake(loser);
             }
         }
     }
 
+    /// Closes the part of the board the safe zone just shrank away from. It
+    /// turns into walls, taking any fruit and snakes still in it.
+    pub(crate) fn update_safe_zone(&mut self) {
+        let Some(safe_zone) = self.safe_zone.as_mut() else {
+            return;
+        };
+        let closed = safe_zone.update(self.tick, self.settings.tick_rate);
+        if closed.is_empty() {
+            return;
+        }
+        let zone = safe_zone.current;
+
+        for cell in &closed {
+            if self.occupancy.tile(cell) == Tile::Free {
+                self.occupancy.set_tile(cell, Tile::Wall);
+            }
+            if self.occupancy.has_fruit(cell) {
+                self.occupancy.set_fruit(cell, false);
+            }
+        }
+        self.fruits
+            .retain(|fruit| zone.contains(&Cell::from_pos(&fruit.pos)));
+
+        let tick = self.tick;
+        let deaths = self
+            .snakes
+            .iter()
+            .filter(|(_, snake)| {
+                snake
+                    .positions()
+                    .iter()
+                    .any(|pos| !zone.contains(&Cell::from_pos(pos)))
+            })
+            .map(|(name, _)| {
+                let death = Death {
+                    cause: DeathCause::OutsideZone,
+                    killer: None,
+                    tick,
+                };
+                (name.clone(), death)
+            })
+            .collect();
+        self.resolve_deaths(deaths);
+    }
+
+    /// Counts down a timed round. Once time is up the tiebreak leader wins;
+    /// if several are level, everyone else is out and the leaders go on in
+    /// sudden death.
+    fn update_time_limit(&mut self) {
+        let Some(time_left) = self.time_left.as_mut() else {
+            return;
+        };
+        if *time_left == 0 {
+            return;
+        }
+        *time_left -= 1;
+        if *time_left > 0 {
+            return;
+        }
+
+        let alive: Vec<(String, u64)> = self
+            .snakes
+            .keys()
+            .filter(|name| self.players.get(*name).is_some_and(|p| p.stats.alive()))
+            .map(|name| (name.clone(), self.tiebreak_value(name)))
+            .collect();
+        let best = alive.iter().map(|(_, value)| *value).max().unwrap_or(0);
+
+        let mut leaders = 0;
+        for (name, value) in &alive {
+            if *value == best {
+                leaders += 1;
+            } else {
+                let death = Death {
+                    cause: DeathCause::Timeout,
+                    killer: None,
+                    tick: self.tick,
+                };
+                self.record_death(name, death);
+            }
+        }
+
+        if leaders <= 1 {
+            self.finish_the_game();
+            return;
+        }
+
+        self.sudden_death = true;
+        for (name, value) in &alive {
+            if *value != best {
+                self.remove_snake(name);
+            }
+        }
+    }
+
+    /// No snake can go anywhere once every cell is taken and there is no
+    /// fruit left to eat.
+    fn is_board_full(&self) -> bool {
+        self.occupancy.free_count() == 0 && self.fruits.is_empty()
+    }
+
+    /// Counts another tick of survival for every snake still alive.
+    fn update_stats(&mut self) {
+        for (name, snake) in &self.snakes {
+            let Some(player) = self.players.get_mut(name) else {
+                continue;
+            };
+            let stats = &mut player.stats;
+            if !stats.alive() {
+                continue;
+            }
+
+            stats.survival_ticks += 1;
+            if stats.survival_ticks % self.settings.tick_rate == 0 {
+                stats.score += SCORE_PER_SECOND_ALIVE;
+            }
+            stats.length = snake.len();
+            stats.max_length = stats.max_length.max(snake.len());
+        }
+    }
+
+    /// Applies the speed progression to every snake's own base speed.
+    fn update_snake_speeds(&mut self) {
+        let base = self.settings.snake_ticks_per_move;
+
+        for snake in self.snakes.values_mut() {
+            let speedup = match self.settings.speed_progression {
+                SpeedProgression::Constant => 0,
+                SpeedProgression::ByLength => (snake.len() / SNAKE_SPEEDUP_LENGTH_STEP) as u32,
+                SpeedProgression::ByTime => {
+                    (self.tick / (SNAKE_SPEEDUP_TIME_STEP_SECS * self.settings.tick_rate)) as u32
+                }
+            };
+            snake.set_ticks_per_move(base.saturating_sub(speedup).max(SNAKE_TICKS_PER_MOVE_MIN));
+        }
+    }
+
+    pub(crate) fn begin_countdown(&mut self) {
+        let resuming = matches!(self.state, GameState::Paused);
+        self.state = match self.settings.countdown_secs * self.settings.tick_rate {
+            0 => GameState::Playing,
+            ticks_left => GameState::Countdown {
+                ticks_left,
+                resuming,
+            },
+        };
+    }
+
     pub fn update(&mut self) {
+        self.match_tick += 1;
+
         match &self.state {
             GameState::NotStarted => {
-                if self.players.iter().count() == 2
-                    && self.players.iter().all(|player| {
-                        return player.1.state == PlayerState::Ready;
-                    })
+                if self.players.len() == self.settings.player_count
+                    && self
+                        .players
+                        .values()
+                        .all(|player| player.state == PlayerState::Ready)
                 {
-                    self.state = GameState::Playing;
                     self.start();
+                    self.begin_countdown();
+                }
+            }
+            GameState::Countdown {
+                ticks_left,
+                resuming,
+            } => {
+                // anyone un-readying cancels it: a paused round stays paused,
+                // a round about to start is called off and it's back to the
+                // lobby, abandoning the match as when a player leaves
+                if self
+                    .players
+                    .values()
+                    .any(|player| player.state == PlayerState::NotReady)
+                {
+                    if *resuming {
+                        self.state = GameState::Paused;
+                    } else {
+                        self.reset_game_state();
+                    }
                     return;
                 }
+
+                self.state = match ticks_left - 1 {
+                    0 => GameState::Playing,
+                    ticks_left => GameState::Countdown {
+                        ticks_left,
+                        resuming: *resuming,
+                    },
+                };
             }
             GameState::Playing => {
-                if self.players.iter().any(|player| {
-                    return player.1.state == PlayerState::NotReady;
-                }) {
+                if self
+                    .players
+                    .values()
+                    .any(|player| player.state == PlayerState::NotReady)
+                {
                     self.state = GameState::Paused;
                     return;
                 }
 
-                self.check_collissions();
+                let mode = self.settings.mode.rules();
+                self.tick += 1;
+                mode.on_tick(self);
+                if !matches!(self.state, GameState::Playing) {
+                    return;
+                }
+                self.update_snake_speeds();
 
-                for (_, snake) in self.snakes.iter_mut() {
-                    snake.move_step_tick();
+                for snake in self.snakes.values_mut() {
+                    snake.move_step_tick(&self.map);
+                    if snake.moved() {
+                        let tail = Cell::from_pos(&snake.previous_tail_pos());
+                        let head = Cell::from_pos(&snake.get_head_pos());
+                        if mode.leaves_trail() {
+                            snake.grow();
+                        } else {
+                            self.occupancy.remove_segment(&tail, snake.slot());
+                        }
+                        self.occupancy.add_segment(&head, snake.slot());
+                    }
                 }
 
-                if self.is_server && self.fruit_pos.is_none() {
+                self.check_collissions();
+                self.update_stats();
+                if mode.spawns_fruit() {
                     self.update_fruit_pos();
                 }
+
+                // the tiebreak decides a full board
+                if let GameState::Playing = self.state
+                    && self.is_board_full()
+                {
+                    self.finish_the_game();
+                }
+
+                if let GameState::Playing = self.state {
+                    self.update_time_limit();
+                }
             }
             GameState::Paused => {
-                if self.players.len() == PLAYER_COUNT_MAX {
-                    if self.players.iter().all(|player| {
-                        return player.1.state == PlayerState::Ready;
-                    }) {
-                        self.state = GameState::Playing;
-                        return;
-                    }
+                // whoever left is out of the round, so it goes on without them
+                if self
+                    .players
+                    .values()
+                    .all(|player| player.state == PlayerState::Ready)
+                {
+                    self.begin_countdown();
                 }
             }
             GameState::Finished(_finish_details) => {
-                if self.players.len() < PLAYER_COUNT_MAX {
-                    self.state = GameState::NotStarted;
-                    return;
-                }
-
-                if self.players.iter().all(|player| {
-                    return player.1.state == PlayerState::Ready;
-                }) {
+                if self.players.len() == self.settings.player_count
+                    && self
+                        .players
+                        .values()
+                        .all(|player| player.state == PlayerState::Ready)
+                {
                     self.start();
-                    self.state = GameState::Playing;
-                    return;
+                    self.begin_countdown();
                 }
             }
         }
     }
 
     pub fn handle_input(&mut self, player_name: &str, c: char) {
+        // the rules stay fixed for every round of a match
+        let can_change_settings = self.is_in_lobby() && !self.is_match_in_progress();
         let player_state = &mut self.players.get_mut(player_name).unwrap().state;
 
         match player_state {
             PlayerState::NotReady => match c {
                 ENTER => {
                     *player_state = PlayerState::Ready;
-                    return;
+                    self.events.push(GameEvent::PlayerReady {
+                        player: player_name.to_string(),
+                        ready: true,
+                    });
                 }
+                'g' if can_change_settings => self.select_next_arena(),
+                'r' if can_change_settings => self.reroll_arena_seed(),
+                '+' | '=' if can_change_settings => self.change_speed_setting(true),
+                '-' if can_change_settings => self.change_speed_setting(false),
+                'p' if can_change_settings => self.select_next_speed_progression(),
+                'o' if can_change_settings => self.select_next_mode(),
+                'm' if can_change_settings => self.select_next_match_format(),
+                't' if can_change_settings => self.select_next_time_limit(),
+                'b' if can_change_settings => self.select_next_tiebreak(),
+                'l' if can_change_settings => self.select_next_tail_rule(),
                 _ => {}
             },
             PlayerState::Ready => {
-                match c {
-                    ESCAPE => {
-                        *player_state = PlayerState::NotReady;
-                        return;
-                    }
-                    _ => {}
+                if c == ESCAPE {
+                    *player_state = PlayerState::NotReady;
+                    self.events.push(GameEvent::PlayerReady {
+                        player: player_name.to_string(),
+                        ready: false,
+                    });
+                    return;
                 }
 
-                match &self.state {
-                    GameState::Playing => {
-                        let snake = self.snakes.get_mut(player_name).unwrap();
-                        match c {
-                            'w' => snake.change_direction(Direction::Up),
-                            's' => snake.change_direction(Direction::Down),
-                            'a' => snake.change_direction(Direction::Left),
-                            'd' => snake.change_direction(Direction::Right),
-                            _ => {}
-                        }
+                // turns can be queued while counting down; dead players have no snake
+                if let GameState::Countdown { .. } | GameState::Playing = &self.state
+                    && let Some(snake) = self.snakes.get_mut(player_name)
+                {
+                    match c {
+                        'w' => snake.change_direction(Direction::Up),
+                        's' => snake.change_direction(Direction::Down),
+                        'a' => snake.change_direction(Direction::Left),
+                        'd' => snake.change_direction(Direction::Right),
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
         }
     }
 
     pub fn draw_objects(&self) {
-        for (_, snake) in self.snakes.iter() {
+        for cell in &self.map.walls {
+            let pos = cell.to_pos();
+            mcq::draw_rectangle(pos.x, pos.y, SNAKE_SIZE, SNAKE_SIZE, mcq::BLACK);
+        }
+
+        for cell in &self.map.obstacles {
+            let pos = cell.to_pos();
+            mcq::draw_rectangle(pos.x, pos.y, SNAKE_SIZE, SNAKE_SIZE, mcq::DARKBROWN);
+        }
+
+        for snake in self.snakes.values() {
             snake.draw();
         }
 
-        if let Some(fruit_pos) = self.fruit_pos {
+        for fruit in &self.fruits {
             mcq::draw_rectangle(
-                fruit_pos.x,
-                fruit_pos.y,
+                fruit.pos.x,
+                fruit.pos.y,
                 SNAKE_SIZE,
                 SNAKE_SIZE,
-                mcq::YELLOW,
+                fruit.kind.info().color,
             );
         }
+
+        if let Some(safe_zone) = &self.safe_zone {
+            safe_zone.draw(&self.map);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two ready players; the next update starts the match.
+    fn ready_game(seed: u64, countdown_secs: u64) -> GameCore {
+        let mut game = GameCore::with_seed(true, seed);
+        game.set_map(Map::empty(30, 20));
+        game.settings.countdown_secs = countdown_secs;
+        for name in ["a", "b"] {
+            game.add_player(name);
+            game.handle_input(name, ENTER);
+        }
+        game
+    }
+
+    fn started_game(seed: u64) -> GameCore {
+        let mut game = ready_game(seed, 0);
+        game.update();
+        game
+    }
+
+    /// Keeps the snakes turning so the board sees plenty of changes.
+    fn steer(game: &mut GameCore) {
+        let turn = match game.match_tick / 40 % 4 {
+            0 => 'w',
+            1 => 'a',
+            2 => 's',
+            _ => 'd',
+        };
+        game.handle_input("a", turn);
+    }
+
+    fn round_trip(game: &GameCore) -> GameCore {
+        let bytes = postcard::to_extend(game, Vec::new()).unwrap();
+        let mut rebuilt: GameCore = postcard::from_bytes(&bytes).unwrap();
+        rebuilt.rebuild_occupancy();
+        rebuilt
+    }
+
+    #[test]
+    fn full_game_starts_with_a_snake_for_every_player() {
+        let mut game = GameCore::with_seed(true, 7);
+        game.set_map(Map::empty(30, 20));
+        game.settings.player_count = PLAYER_COUNT_MAX;
+        let names: Vec<String> = (0..PLAYER_COUNT_MAX)
+            .map(|index| format!("player {}", index))
+            .collect();
+        for name in &names {
+            game.add_player(name);
+            game.handle_input(name, ENTER);
+        }
+        game.update();
+
+        assert!(matches!(game.state, GameState::Countdown { .. }));
+        let heads: Vec<MyVec2> = game.snakes.values().map(Snake::get_head_pos).collect();
+        assert_eq!(heads.len(), PLAYER_COUNT_MAX);
+        for (index, head) in heads.iter().enumerate() {
+            assert!(!heads[index + 1..].contains(head));
+        }
+    }
+
+    #[test]
+    fn countdown_lasts_as_long_at_any_tick_rate() {
+        for tick_rate in [60, TICK_RATE_FREQ, 240] {
+            let mut game = ready_game(7, COUNTDOWN_SECS);
+            game.settings.tick_rate = tick_rate;
+            game.update();
+            assert!(matches!(
+                game.state,
+                GameState::Countdown { ticks_left, .. } if ticks_left == COUNTDOWN_SECS * tick_rate
+            ));
+        }
+    }
+
+    #[test]
+    fn cancelled_countdown_goes_back_to_the_lobby_or_the_pause() {
+        let mut game = ready_game(7, 1);
+        game.update();
+        assert!(matches!(game.state, GameState::Countdown { .. }));
+
+        game.handle_input("a", ESCAPE);
+        game.update();
+        assert!(matches!(game.state, GameState::NotStarted));
+        assert!(game.snakes.is_empty());
+
+        game.handle_input("a", ENTER);
+        for _ in 0..=game.settings.tick_rate {
+            game.update();
+        }
+        game.handle_input("a", ESCAPE);
+        game.update();
+        assert!(matches!(game.state, GameState::Paused));
+
+        game.handle_input("a", ENTER);
+        game.update();
+        game.handle_input("a", ESCAPE);
+        game.update();
+        assert!(matches!(game.state, GameState::Paused));
+        assert_eq!(game.snakes.len(), 2);
+    }
+
+    #[test]
+    fn paused_round_resumes_without_a_player_who_left() {
+        let mut game = GameCore::with_seed(true, 7);
+        game.set_map(Map::empty(30, 20));
+        game.settings.countdown_secs = 0;
+        game.settings.player_count = 3;
+        for name in ["a", "b", "c"] {
+            game.add_player(name);
+            game.handle_input(name, ENTER);
+        }
+        game.update();
+        assert!(matches!(game.state, GameState::Playing));
+
+        game.handle_input("a", ESCAPE);
+        game.update();
+        assert!(matches!(game.state, GameState::Paused));
+
+        game.remove_player("c");
+        game.handle_input("a", ENTER);
+        game.update();
+        assert!(matches!(game.state, GameState::Playing));
+        assert_eq!(game.snakes.len(), 2);
+    }
+
+    #[test]
+    fn settings_are_locked_between_rounds_of_a_match() {
+        let mut game = ready_game(7, 0);
+        game.settings.match_format = MatchFormat::BestOf(3);
+        game.update();
+        let death = Death {
+            cause: DeathCause::Timeout,
+            killer: None,
+            tick: game.tick,
+        };
+        game.resolve_deaths(BTreeMap::from([(String::from("b"), death)]));
+        game.update();
+        assert!(game.is_match_in_progress());
+
+        let settings = format!("{:?}", game.settings);
+        for key in ['g', 'r', '+', '-', 'p', 'o', 'm', 't', 'b', 'l'] {
+            game.handle_input("a", key);
+        }
+        assert_eq!(format!("{:?}", game.settings), settings);
+        assert!(matches!(game.arena, ArenaSource::Loaded));
+    }
+
+    #[test]
+    fn game_rebuilt_from_an_update_stays_in_step() {
+        let mut game = started_game(7);
+        for _ in 0..300 {
+            steer(&mut game);
+            game.update();
+        }
+
+        let mut rebuilt = round_trip(&game);
+        let mut fruit_spawned = false;
+        for _ in 0..1500 {
+            steer(&mut game);
+            steer(&mut rebuilt);
+            game.update();
+            rebuilt.update();
+
+            assert_eq!(game.state_hash(), rebuilt.state_hash());
+            fruit_spawned |= game
+                .drain_events()
+                .iter()
+                .any(|event| matches!(event, GameEvent::FruitSpawned { .. }));
+            rebuilt.drain_events();
+        }
+        assert!(fruit_spawned);
     }
 }
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::MyVec2;
+use crate::fruit::FruitKind;
+use crate::game_core::{Death, FinishDetails};
+
+/// Something that happened during a game, for logging and for the client
+/// to show. Collected by `GameCore` until drained.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GameEvent {
+    PlayerReady {
+        player: String,
+        ready: bool,
+    },
+    RoundStarted {
+        round: u32,
+    },
+    FruitSpawned {
+        kind: FruitKind,
+        pos: MyVec2,
+    },
+    FruitEaten {
+        player: String,
+        kind: FruitKind,
+    },
+    /// A fruit with a lasting effect was eaten.
+    PowerUpActivated {
+        player: String,
+        kind: FruitKind,
+        duration_ticks: u32,
+    },
+    SnakeDied {
+        player: String,
+        death: Death,
+    },
+    GameFinished {
+        details: FinishDetails,
+    },
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fruit::{Fruit, FruitEffect};
+use crate::game_core::{DeathCause, FinishDetails, GameCore, PlayerColission, Standing};
+use crate::map::{Map, SpawnPoint};
+use crate::safe_zone::SafeZone;
+use crate::snake::SnakesColission;
+
+/// A player killed by a collision, with the cause and the killer if any.
+pub type Casualty = (String, DeathCause, Option<String>);
+
+/// Rules of a game mode. `GameCore` drives the round and asks the mode
+/// whenever a rule may differ between modes; the defaults are the classic
+/// rules.
+pub trait GameMode: Sync {
+    /// Where the snakes start, in player order.
+    fn spawn_layout(&self, map: &Map) -> Vec<SpawnPoint> {
+        map.spawns.clone()
+    }
+
+    /// Called at the start of every round, once the snakes are in place.
+    fn on_round_start(&self, _game: &mut GameCore) {}
+
+    /// Called on every tick of play, before the snakes move.
+    fn on_tick(&self, _game: &mut GameCore) {}
+
+    /// Whether snakes keep their tail when they step, growing every time.
+    fn leaves_trail(&self) -> bool {
+        false
+    }
+
+    fn spawns_fruit(&self) -> bool {
+        true
+    }
+
+    /// What eating `fruit` does to a snake.
+    fn fruit_effect(&self, fruit: &Fruit) -> FruitEffect {
+        fruit.kind.info().effect
+    }
+
+    /// Who dies of a collision, and of what.
+    fn collision_outcome(&self, colission: PlayerColission) -> Vec<Casualty> {
+        match colission {
+            PlayerColission::SelfColission(player_name) => {
+                vec![(player_name, DeathCause::SelfColission, None)]
+            }
+            PlayerColission::WallColission(player_name) => {
+                vec![(player_name, DeathCause::Wall, None)]
+            }
+            PlayerColission::ObstacleColission(player_name) => {
+                vec![(player_name, DeathCause::Obstacle, None)]
+            }
+            PlayerColission::InBetween(SnakesColission::HeadToHeadColission, loser, other) => vec![
+                (loser, DeathCause::HeadToHead, None),
+                (other, DeathCause::HeadToHead, None),
+            ],
+            PlayerColission::InBetween(SnakesColission::HeadToTailColission, loser, other) => {
+                vec![(loser, DeathCause::OtherTail, Some(other))]
+            }
+        }
+    }
+
+    /// Whether the round ends with this many snakes left alive.
+    fn is_round_over(&self, survivors: usize) -> bool {
+        survivors <= 1
+    }
+
+    /// Final positions when the round ends, first place first.
+    fn standings(&self, game: &GameCore) -> Vec<Standing> {
+        game.ranked_by_survival()
+    }
+
+    /// Players the round counts as a win for in the match. Only an outright
+    /// winner scores; a draw counts for nobody.
+    fn round_winners(&self, details: &FinishDetails) -> Vec<String> {
+        match details.winners()[..] {
+            [winner] => vec![winner.to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Mode as chosen in the lobby and sent to clients.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GameModeKind {
+    Classic,
+    /// The board shrinks to a safe zone as the round goes on.
+    BattleRoyale,
+    /// No fruit; snakes grow on every step and leave a trail behind.
+    LightCycle,
+}
+
+impl GameModeKind {
+    pub fn rules(&self) -> &'static dyn GameMode {
+        match self {
+            GameModeKind::Classic => &Classic,
+            GameModeKind::BattleRoyale => &BattleRoyale,
+            GameModeKind::LightCycle => &LightCycle,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameModeKind::Classic => "classic",
+            GameModeKind::BattleRoyale => "battle royale",
+            GameModeKind::LightCycle => "light cycle",
+        }
+    }
+
+    /// Looks a mode up by its name, with dashes or underscores allowed in
+    /// place of spaces, e.g. "battle-royale".
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase().replace(['-', '_'], " ");
+        [
+            GameModeKind::Classic,
+            GameModeKind::BattleRoyale,
+            GameModeKind::LightCycle,
+        ]
+        .into_iter()
+        .find(|mode| mode.name() == name)
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            GameModeKind::Classic => GameModeKind::BattleRoyale,
+            GameModeKind::BattleRoyale => GameModeKind::LightCycle,
+            GameModeKind::LightCycle => GameModeKind::Classic,
+        }
+    }
+}
+
+pub struct Classic;
+
+impl GameMode for Classic {}
+
+pub struct BattleRoyale;
+
+impl GameMode for BattleRoyale {
+    fn on_round_start(&self, game: &mut GameCore) {
+        game.safe_zone = Some(SafeZone::new(game.map(), game.settings.tick_rate));
+    }
+
+    fn on_tick(&self, game: &mut GameCore) {
+        game.update_safe_zone();
+    }
+}
+
+pub struct LightCycle;
+
+impl GameMode for LightCycle {
+    fn leaves_trail(&self) -> bool {
+        true
+    }
+
+    fn spawns_fruit(&self) -> bool {
+        false
+    }
+}
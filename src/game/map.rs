@@ -0,0 +1,460 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::MyVec2,
+    snake::Direction,
+    snake_cfg::{
+        BOARD_HEIGHT, BOARD_WIDTH, PLAYER_COUNT_MAX, SCREEN_HEIGHT, SCREEN_WIDTH, SNAKE_SIZE,
+    },
+};
+
+const LAYOUT_FREE: char = '.';
+const LAYOUT_WALL: char = '#';
+const LAYOUT_OBSTACLE: char = 'X';
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cell {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Cell {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn from_pos(pos: &MyVec2) -> Self {
+        Self {
+            x: (pos.x / SNAKE_SIZE).round() as i32,
+            y: (pos.y / SNAKE_SIZE).round() as i32,
+        }
+    }
+
+    pub fn to_pos(self) -> MyVec2 {
+        MyVec2::new(self.x as f32 * SNAKE_SIZE, self.y as f32 * SNAKE_SIZE)
+    }
+
+    pub fn step(self, direction: Direction) -> Self {
+        match direction {
+            Direction::Up => Self::new(self.x, self.y - 1),
+            Direction::Down => Self::new(self.x, self.y + 1),
+            Direction::Left => Self::new(self.x - 1, self.y),
+            Direction::Right => Self::new(self.x + 1, self.y),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Tile {
+    Free,
+    Wall,
+    Obstacle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpawnPoint {
+    pub cell: Cell,
+    pub direction: Direction,
+}
+
+/// Rectangular area of the board, in cells.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Zone {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Zone {
+    pub fn contains(&self, cell: &Cell) -> bool {
+        cell.x >= self.x
+            && cell.x < self.x + self.width
+            && cell.y >= self.y
+            && cell.y < self.y + self.height
+    }
+
+    pub fn cells(self) -> impl Iterator<Item = Cell> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| Cell::new(x, y)))
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    InvalidFormat(String),
+    InvalidMap(String),
+}
+
+/// Map as stored on disk. The board size is taken from the layout, where
+/// '.' is a free cell, '#' a wall and 'X' an obstacle.
+#[derive(Deserialize)]
+struct MapFile {
+    name: String,
+    layout: Vec<String>,
+    spawns: Vec<SpawnPoint>,
+    #[serde(default)]
+    fruit_zones: Vec<Zone>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Map {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub walls: Vec<Cell>,
+    pub obstacles: Vec<Cell>,
+    pub spawns: Vec<SpawnPoint>,
+    pub fruit_zones: Vec<Zone>,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::empty(BOARD_WIDTH, BOARD_HEIGHT)
+    }
+}
+
+/// Players in pairs mirrored through the centre of the board, the first of
+/// each pair on the left heading right and the second facing it, so the
+/// first two players always start opposite each other.
+pub fn default_spawns(width: i32, height: i32) -> Vec<SpawnPoint> {
+    let pairs = PLAYER_COUNT_MAX.div_ceil(2) as i32;
+    (0..pairs)
+        .flat_map(|pair| {
+            let cell = Cell::new(width / 4, height * (pair + 1) / (pairs + 1));
+            [
+                SpawnPoint {
+                    cell,
+                    direction: Direction::Right,
+                },
+                SpawnPoint {
+                    cell: Cell::new(width - 1 - cell.x, height - 1 - cell.y),
+                    direction: Direction::Left,
+                },
+            ]
+        })
+        .take(PLAYER_COUNT_MAX)
+        .collect()
+}
+
+impl Map {
+    /// Board without any walls.
+    pub fn empty(width: i32, height: i32) -> Self {
+        Self {
+            name: "empty".to_string(),
+            width,
+            height,
+            walls: Vec::new(),
+            obstacles: Vec::new(),
+            spawns: default_spawns(width, height),
+            fruit_zones: Vec::new(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, MapError> {
+        let map_file: MapFile =
+            serde_json::from_str(json).map_err(|err| MapError::InvalidFormat(err.to_string()))?;
+
+        let height = map_file.layout.len() as i32;
+        let width = map_file.layout.first().map_or(0, |row| row.chars().count()) as i32;
+
+        let mut walls = Vec::new();
+        let mut obstacles = Vec::new();
+
+        for (y, row) in map_file.layout.iter().enumerate() {
+            if row.chars().count() as i32 != width {
+                return Err(MapError::InvalidFormat(format!(
+                    "layout row {} has {} cells, expected {}",
+                    y,
+                    row.chars().count(),
+                    width
+                )));
+            }
+
+            for (x, tile) in row.chars().enumerate() {
+                let cell = Cell::new(x as i32, y as i32);
+                match tile {
+                    LAYOUT_FREE => {}
+                    LAYOUT_WALL => walls.push(cell),
+                    LAYOUT_OBSTACLE => obstacles.push(cell),
+                    _ => {
+                        return Err(MapError::InvalidFormat(format!(
+                            "unknown tile '{}' at ({}, {})",
+                            tile, x, y
+                        )));
+                    }
+                }
+            }
+        }
+
+        let map = Self {
+            name: map_file.name,
+            width,
+            height,
+            walls,
+            obstacles,
+            spawns: map_file.spawns,
+            fruit_zones: map_file.fruit_zones,
+        };
+
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        let json = fs::read_to_string(path).map_err(MapError::Io)?;
+        Self::from_json(&json)
+    }
+
+    /// Loads every `.json` map in `dir`, sorted by file name. Maps that fail
+    /// to load are reported and skipped.
+    pub fn load_dir(dir: &Path) -> Vec<Self> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!(
+                    "[WARNING]: failed to read map directory {}: {}",
+                    dir.display(),
+                    err
+                );
+                return Vec::new();
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut maps = Vec::new();
+        for path in paths {
+            match Self::load(&path) {
+                Ok(map) => maps.push(map),
+                Err(err) => {
+                    eprintln!("[WARNING]: skipping map {}: {:?}", path.display(), err);
+                }
+            }
+        }
+        maps
+    }
+
+    fn validate(&self) -> Result<(), MapError> {
+        if self.width <= 0 || self.height <= 0 {
+            return Err(MapError::InvalidMap("layout is empty".to_string()));
+        }
+
+        if self.width as f32 * SNAKE_SIZE > SCREEN_WIDTH
+            || self.height as f32 * SNAKE_SIZE > SCREEN_HEIGHT
+        {
+            return Err(MapError::InvalidMap(format!(
+                "board {}x{} does not fit on the screen",
+                self.width, self.height
+            )));
+        }
+
+        if self.spawns.len() < PLAYER_COUNT_MAX {
+            return Err(MapError::InvalidMap(format!(
+                "map has {} spawn points, {} required",
+                self.spawns.len(),
+                PLAYER_COUNT_MAX
+            )));
+        }
+
+        for spawn in &self.spawns {
+            // a new snake occupies its spawn cell and the cell in front of it
+            let head = self.wrap(spawn.cell.step(spawn.direction));
+            if !self.contains(&spawn.cell) || self.tile_at(&spawn.cell) != Tile::Free {
+                return Err(MapError::InvalidMap(format!(
+                    "spawn point ({}, {}) is not on a free cell",
+                    spawn.cell.x, spawn.cell.y
+                )));
+            }
+            if self.tile_at(&head) != Tile::Free {
+                return Err(MapError::InvalidMap(format!(
+                    "spawn point ({}, {}) faces a blocked cell",
+                    spawn.cell.x, spawn.cell.y
+                )));
+            }
+        }
+
+        for zone in &self.fruit_zones {
+            if zone.width <= 0
+                || zone.height <= 0
+                || zone.x < 0
+                || zone.y < 0
+                || zone.x + zone.width > self.width
+                || zone.y + zone.height > self.height
+            {
+                return Err(MapError::InvalidMap(format!(
+                    "fruit zone at ({}, {}) is outside of the board",
+                    zone.x, zone.y
+                )));
+            }
+
+            let has_free_cell = (zone.y..zone.y + zone.height)
+                .flat_map(|y| (zone.x..zone.x + zone.width).map(move |x| Cell::new(x, y)))
+                .any(|cell| !self.is_blocked(&cell));
+            if !has_free_cell {
+                return Err(MapError::InvalidMap(format!(
+                    "fruit zone at ({}, {}) has no free cells",
+                    zone.x, zone.y
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn contains(&self, cell: &Cell) -> bool {
+        cell.x >= 0 && cell.x < self.width && cell.y >= 0 && cell.y < self.height
+    }
+
+    /// Wraps a cell that left the board back to the opposite edge.
+    pub fn wrap(&self, cell: Cell) -> Cell {
+        Cell::new(
+            cell.x.rem_euclid(self.width),
+            cell.y.rem_euclid(self.height),
+        )
+    }
+
+    pub fn tile_at(&self, cell: &Cell) -> Tile {
+        if self.walls.contains(cell) {
+            Tile::Wall
+        } else if self.obstacles.contains(cell) {
+            Tile::Obstacle
+        } else {
+            Tile::Free
+        }
+    }
+
+    pub fn is_blocked(&self, cell: &Cell) -> bool {
+        self.tile_at(cell) != Tile::Free
+    }
+
+    /// Whether fruit may spawn in `cell`, ignoring what is on it.
+    pub fn in_fruit_zone(&self, cell: &Cell) -> bool {
+        self.fruit_zones.is_empty() || self.fruit_zones.iter().any(|zone| zone.contains(cell))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: [&str; 4] = ["........", ".X....#.", "........", "........"];
+
+    fn spawns() -> serde_json::Value {
+        serde_json::json!([
+            { "cell": { "x": 0, "y": 0 }, "direction": "Right" },
+            { "cell": { "x": 7, "y": 3 }, "direction": "Left" },
+            { "cell": { "x": 0, "y": 2 }, "direction": "Right" },
+            { "cell": { "x": 7, "y": 2 }, "direction": "Left" },
+        ])
+    }
+
+    fn map_json(
+        layout: &[&str],
+        spawns: serde_json::Value,
+        fruit_zones: serde_json::Value,
+    ) -> String {
+        serde_json::json!({
+            "name": "test",
+            "layout": layout,
+            "spawns": spawns,
+            "fruit_zones": fruit_zones,
+        })
+        .to_string()
+    }
+
+    fn load(
+        layout: &[&str],
+        spawns: serde_json::Value,
+        fruit_zones: serde_json::Value,
+    ) -> Result<Map, MapError> {
+        Map::from_json(&map_json(layout, spawns, fruit_zones))
+    }
+
+    fn with_spawn(index: usize, x: i32, y: i32, direction: &str) -> serde_json::Value {
+        let mut spawns = spawns();
+        spawns[index] = serde_json::json!({ "cell": { "x": x, "y": y }, "direction": direction });
+        spawns
+    }
+
+    #[test]
+    fn valid_map_loads() {
+        let zones = serde_json::json!([{ "x": 0, "y": 0, "width": 2, "height": 2 }]);
+        let map = load(&LAYOUT, spawns(), zones).unwrap();
+
+        assert_eq!((map.width, map.height), (8, 4));
+        assert_eq!(map.tile_at(&Cell::new(1, 1)), Tile::Obstacle);
+        assert_eq!(map.tile_at(&Cell::new(6, 1)), Tile::Wall);
+        assert_eq!(map.spawns.len(), PLAYER_COUNT_MAX);
+        assert_eq!(map.fruit_zones.len(), 1);
+    }
+
+    #[test]
+    fn uneven_rows_are_rejected() {
+        let layout = ["........", ".......", "........", "........"];
+        assert!(matches!(
+            load(&layout, spawns(), serde_json::json!([])),
+            Err(MapError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_tile_is_rejected() {
+        let layout = ["........", "...?....", "........", "........"];
+        assert!(matches!(
+            load(&layout, spawns(), serde_json::json!([])),
+            Err(MapError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn too_few_spawns_are_rejected() {
+        let mut spawns = spawns();
+        spawns.as_array_mut().unwrap().pop();
+        assert!(matches!(
+            load(&LAYOUT, spawns, serde_json::json!([])),
+            Err(MapError::InvalidMap(_))
+        ));
+    }
+
+    #[test]
+    fn spawn_on_a_blocked_cell_is_rejected() {
+        assert!(matches!(
+            load(&LAYOUT, with_spawn(0, 1, 1, "Right"), serde_json::json!([])),
+            Err(MapError::InvalidMap(_))
+        ));
+    }
+
+    #[test]
+    fn spawn_facing_a_blocked_cell_is_rejected() {
+        assert!(matches!(
+            load(&LAYOUT, with_spawn(0, 5, 1, "Right"), serde_json::json!([])),
+            Err(MapError::InvalidMap(_))
+        ));
+    }
+
+    #[test]
+    fn fruit_zone_outside_the_board_is_rejected() {
+        let zones = serde_json::json!([{ "x": 6, "y": 2, "width": 3, "height": 2 }]);
+        assert!(matches!(
+            load(&LAYOUT, spawns(), zones),
+            Err(MapError::InvalidMap(_))
+        ));
+    }
+
+    #[test]
+    fn fruit_zone_without_free_cells_is_rejected() {
+        let zones = serde_json::json!([{ "x": 1, "y": 1, "width": 1, "height": 1 }]);
+        assert!(matches!(
+            load(&LAYOUT, spawns(), zones),
+            Err(MapError::InvalidMap(_))
+        ));
+    }
+}
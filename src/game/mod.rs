@@ -1,37 +1,112 @@
+pub mod arena;
+pub mod desync;
+pub mod fruit;
 pub mod game_core;
-use crate::{game_core::*, snake_cfg::PLAYER_COUNT_MAX};
+pub mod game_event;
+pub mod game_mode;
+pub mod game_rng;
+pub mod map;
+pub mod occupancy;
+pub mod safe_zone;
+use crate::{desync::DesyncCheck, game_core::*, game_event::GameEvent};
 use macroquad::{color::Color, prelude as mcq};
+use std::collections::VecDeque;
+use std::path::PathBuf;
 pub mod snake;
 
+const KILL_FEED_LEN: usize = 4;
 
 #[derive(Debug)]
 pub struct GameLocal {
     pub game_core: GameCore,
-    player_name: String,
+    /// `None` when only watching, as in replays.
+    player_name: Option<String>,
+    /// Latest deaths of the round, oldest first.
+    kill_feed: VecDeque<String>,
+    desync: DesyncCheck,
+    show_debug_overlay: bool,
+    /// Message of the day sent by the server, shown in the lobby.
+    motd: Option<String>,
 }
 
 impl GameLocal {
-    pub fn new(player_name: &str) -> Self {
+    /// `desync_dump_dir` is where both states are written whenever the
+    /// prediction and the server disagree.
+    pub fn new(player_name: &str, desync_dump_dir: Option<PathBuf>) -> Self {
         let mut game_core = GameCore::new(false);
         game_core.add_player(player_name);
 
         Self {
-            game_core: game_core,
-            player_name: player_name.to_string(),
+            game_core,
+            player_name: Some(player_name.to_string()),
+            kill_feed: VecDeque::new(),
+            desync: DesyncCheck::new(desync_dump_dir),
+            show_debug_overlay: false,
+            motd: None,
+        }
+    }
+
+    pub fn spectator(game_core: GameCore) -> Self {
+        Self {
+            game_core,
+            player_name: None,
+            kill_feed: VecDeque::new(),
+            desync: DesyncCheck::new(None),
+            show_debug_overlay: false,
+            motd: None,
         }
     }
 
     pub fn update(&mut self) {
         self.game_core.update();
+        // only the events sent by the server count, not predicted ones
+        self.game_core.drain_events();
+        self.desync.record_prediction(&self.game_core);
+    }
+
+    pub fn set_server_state(&mut self, mut game_core: GameCore) {
+        self.desync.on_server_state(&game_core);
+        game_core.rebuild_occupancy();
+        self.game_core = game_core;
+    }
+
+    pub fn check_state_hash(&mut self, match_tick: u64, hash: u64) {
+        self.desync.check(match_tick, hash);
+    }
+
+    pub fn set_motd(&mut self, motd: String) {
+        self.motd = Some(motd);
+    }
+
+    pub fn toggle_debug_overlay(&mut self) {
+        self.show_debug_overlay = !self.show_debug_overlay;
+    }
+
+    pub fn handle_events(&mut self, events: Vec<GameEvent>) {
+        for event in events {
+            match event {
+                GameEvent::RoundStarted { .. } => self.kill_feed.clear(),
+                GameEvent::SnakeDied { player, death } => {
+                    self.kill_feed
+                        .push_back(format!("{} {}", player, death.description()));
+                    if self.kill_feed.len() > KILL_FEED_LEN {
+                        self.kill_feed.pop_front();
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 
     pub fn handle_input(&mut self, c: char) {
-        self.game_core.handle_input(&self.player_name, c);
+        if let Some(player_name) = &self.player_name {
+            self.game_core.handle_input(player_name, c);
+        }
     }
 
     pub fn get_players_status_text(&self) -> String {
         let mut text = String::new();
-        for (_, player) in &self.game_core.players {
+        for player in self.game_core.players.values() {
             let state_str = {
                 if player.state == PlayerState::NotReady {
                     "not ready"
@@ -45,6 +120,104 @@ impl GameLocal {
         text
     }
 
+    /// One line per player, best score first.
+    pub fn get_scoreboard_text(&self) -> String {
+        let mut players: Vec<&Player> = self.game_core.players.values().collect();
+        players.sort_by_key(|player| std::cmp::Reverse(player.stats.score));
+
+        let mut text = String::new();
+        if self.game_core.sudden_death {
+            text += "SUDDEN DEATH\n";
+        } else if let Some(time_left) = self.game_core.time_left {
+            let seconds = time_left.div_ceil(self.game_core.settings.tick_rate);
+            text += format!("time left {}:{:02}\n", seconds / 60, seconds % 60).as_str();
+        }
+        for player in players {
+            let stats = &player.stats;
+            let status = if stats.alive() { "" } else { " (dead)" };
+            text += format!(
+                "{}: {} pts, length {}{}\n",
+                player.name, stats.score, stats.length, status
+            )
+            .as_str();
+        }
+        text
+    }
+
+    /// Final places, with how and when each player died.
+    pub fn get_standings_text(&self, finish_details: &FinishDetails) -> String {
+        let mut text = String::new();
+        for standing in &finish_details.standings {
+            let fate = match &standing.death {
+                None => String::from("survived"),
+                Some(death) => format!(
+                    "{} at {:.1}s",
+                    death.description(),
+                    death.tick as f32 / self.game_core.settings.tick_rate as f32
+                ),
+            };
+            text += format!("{}. {}: {}\n", standing.place, standing.name, fate).as_str();
+        }
+        text
+    }
+
+    pub fn get_stats_text(&self) -> String {
+        let mut text = String::new();
+        for player in self.game_core.players.values() {
+            let stats = &player.stats;
+            text += format!(
+                "{}: {} pts - {} fruits, length {} (max {}), {} kills, {}s alive\n",
+                player.name,
+                stats.score,
+                stats.fruits_eaten,
+                stats.length,
+                stats.max_length,
+                stats.kills,
+                stats.survival_seconds(self.game_core.settings.tick_rate)
+            )
+            .as_str();
+        }
+        text
+    }
+
+    pub fn get_settings_text(&self) -> String {
+        format!(
+            "mode: {}, [o] change\narena: {}\n[g] change arena, [r] new seed\nspeed: {}\n[+/-] change speed, [p] change progression\nmatch: {}, [m] change\ntime limit: {}\n[t] change limit, [b] change tiebreak\ncollisions: {}, [l] change\n",
+            self.game_core.settings.mode.name(),
+            self.game_core.arena_description(),
+            self.game_core.speed_description(),
+            self.game_core.settings.match_format.name(),
+            self.game_core.time_limit_description(),
+            self.game_core.settings.tail_rule.name()
+        )
+    }
+
+    /// Round wins so far and what comes next. Empty for single round matches.
+    pub fn get_match_text(&self, finish_details: &FinishDetails) -> String {
+        let match_state = &self.game_core.match_state;
+        let match_format = self.game_core.settings.match_format;
+        if match_format == MatchFormat::BestOf(1) {
+            return String::new();
+        }
+
+        let round_wins: Vec<String> = self
+            .game_core
+            .players
+            .keys()
+            .map(|name| format!("{} {}", name, match_state.wins(name)))
+            .collect();
+        let mut text = format!(
+            "round {} of {}, round wins: {}\n",
+            match_state.round,
+            match_format.name(),
+            round_wins.join(", ")
+        );
+        if !finish_details.match_over {
+            text += format!("ready up for round {}\n", match_state.round + 1).as_str();
+        }
+        text
+    }
+
     pub fn draw(&self) {
         mcq::clear_background(mcq::RED);
         match &self.game_core.state {
@@ -59,12 +232,18 @@ impl GameLocal {
                 self.game_core.draw_objects();
 
                 let mut text = String::new();
+                if let Some(motd) = &self.motd {
+                    text = text + motd + "\n\n";
+                }
 
                 let players = &self.game_core.players;
-                if players.len() != PLAYER_COUNT_MAX {
-                    text = String::from("Waiting for all players\n");
+                if players.len() != self.game_core.settings.player_count {
+                    text += "Waiting for all players\n";
                 }
                 text = text + &self.get_players_status_text();
+                if let GameState::NotStarted = self.game_core.state {
+                    text = text + "\n" + &self.get_settings_text();
+                }
 
                 mcq::draw_multiline_text(&text, 20.0, 100.0, 30.0, None, mcq::RED);
             }
@@ -72,21 +251,43 @@ impl GameLocal {
                 let background_color: Color;
                 let mut game_status_text = String::new();
 
-                if finish_details.draw {
-                    game_status_text += "It's a draw!\n";
+                // once a longer match is decided, its result is what matters
+                let match_text = self.get_match_text(finish_details);
+                let (winners, what) = if finish_details.match_over && !match_text.is_empty() {
+                    (self.game_core.match_state.leaders(), " the match")
+                } else {
+                    (finish_details.winners(), "")
+                };
+
+                if winners.len() != 1 {
+                    game_status_text +=
+                        format!("It's a draw{}: {}!\n", what, winners.join(", ")).as_str();
                     background_color = mcq::DARKGRAY;
                 } else {
-                    if finish_details.winner == self.player_name {
-                        game_status_text += format!("You win!\n").as_str();
+                    if self.player_name.as_deref() == Some(winners[0]) {
+                        game_status_text += format!("You win{}!\n", what).as_str();
                         background_color = mcq::DARKBLUE;
                     } else {
                         game_status_text +=
-                            format!("Player {} won!\n", finish_details.winner).as_str();
+                            format!("Player {} won{}!\n", winners[0], what).as_str();
                         background_color = mcq::RED;
                     }
                 }
 
-                let player_status_text = self.get_players_status_text();
+                let breakdown_text = match_text
+                    + &self.get_standings_text(finish_details)
+                    + "\n"
+                    + &self.get_stats_text();
+
+                let mut player_status_text = String::new();
+                if self.game_core.players.len() != self.game_core.settings.player_count {
+                    player_status_text = String::from("Waiting for all players\n");
+                }
+                player_status_text += &self.get_players_status_text();
+                // settings are locked until the match is over
+                if finish_details.match_over {
+                    player_status_text = player_status_text + "\n" + &self.get_settings_text();
+                }
 
                 mcq::draw_rectangle(
                     0.0,
@@ -95,11 +296,55 @@ impl GameLocal {
                     mcq::screen_height(),
                     background_color,
                 );
-                mcq::draw_multiline_text(&game_status_text, 20.0, 100.0, 30.0, None, mcq::BLACK);
-                mcq::draw_multiline_text(&player_status_text, 20.0, 300.0, 30.0, None, mcq::BLACK);
+                mcq::draw_multiline_text(&game_status_text, 20.0, 60.0, 30.0, None, mcq::BLACK);
+                mcq::draw_multiline_text(&breakdown_text, 20.0, 110.0, 20.0, None, mcq::BLACK);
+                mcq::draw_multiline_text(&player_status_text, 20.0, 300.0, 20.0, None, mcq::BLACK);
                 self.game_core.draw_objects();
             }
-            _ => self.game_core.draw_objects(),
+            GameState::Countdown { ticks_left, .. } => {
+                self.game_core.draw_objects();
+
+                let seconds_left = ticks_left.div_ceil(self.game_core.settings.tick_rate);
+                let text = seconds_left.to_string();
+                let size = mcq::measure_text(&text, None, 120, 1.0);
+                mcq::draw_text(
+                    &text,
+                    (mcq::screen_width() - size.width) / 2.0,
+                    (mcq::screen_height() + size.height) / 2.0,
+                    120.0,
+                    mcq::WHITE,
+                );
+            }
+            GameState::Playing => {
+                self.game_core.draw_objects();
+                mcq::draw_multiline_text(
+                    self.get_scoreboard_text(),
+                    10.0,
+                    20.0,
+                    20.0,
+                    None,
+                    mcq::WHITE,
+                );
+
+                let kill_feed: Vec<&str> = self.kill_feed.iter().map(String::as_str).collect();
+                mcq::draw_multiline_text(
+                    kill_feed.join("\n"),
+                    10.0,
+                    mcq::screen_height() - 20.0 * KILL_FEED_LEN as f32,
+                    20.0,
+                    None,
+                    mcq::WHITE,
+                );
+            }
+        }
+        if self.show_debug_overlay {
+            mcq::draw_text(
+                self.desync.get_overlay_text(),
+                mcq::screen_width() - 360.0,
+                20.0,
+                20.0,
+                mcq::WHITE,
+            );
         }
         mcq::draw_fps();
     }
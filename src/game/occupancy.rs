@@ -0,0 +1,158 @@
+use rand::Rng;
+
+use crate::map::{Cell, Map, Tile};
+
+/// Per-cell view of the board, kept up to date as snakes move and fruit
+/// comes and goes, so cell queries don't have to scan every snake.
+///
+/// Snakes are told apart by their slot, a small index handed out when the
+/// game starts. A snake can have several segments in one cell right after
+/// growing, so segments are counted rather than flagged.
+///
+/// Everything here follows from the snakes, fruit and map, so the grid is
+/// never sent or saved, only rebuilt.
+#[derive(Debug, Clone, Default)]
+pub struct Occupancy {
+    width: i32,
+    slots: usize,
+    tiles: Vec<Tile>,
+    fruit: Vec<bool>,
+    /// Segment count for every (cell, slot) pair.
+    segments: Vec<u16>,
+    /// Segment count for every cell, over all slots.
+    segments_total: Vec<u16>,
+    /// Cells with no tile, snake or fruit, for every row.
+    free_in_row: Vec<usize>,
+    free_total: usize,
+}
+
+impl Occupancy {
+    pub fn new(map: &Map, slots: usize) -> Self {
+        let cell_count = (map.width * map.height) as usize;
+        let mut occupancy = Self {
+            width: map.width,
+            slots,
+            tiles: vec![Tile::Free; cell_count],
+            fruit: vec![false; cell_count],
+            segments: vec![0; cell_count * slots],
+            segments_total: vec![0; cell_count],
+            free_in_row: vec![map.width as usize; map.height as usize],
+            free_total: cell_count,
+        };
+
+        for cell in &map.walls {
+            occupancy.set_tile(cell, Tile::Wall);
+        }
+        for cell in &map.obstacles {
+            occupancy.set_tile(cell, Tile::Obstacle);
+        }
+
+        occupancy
+    }
+
+    fn index(&self, cell: &Cell) -> usize {
+        (cell.y * self.width + cell.x) as usize
+    }
+
+    fn cell_at(&self, index: usize) -> Cell {
+        Cell::new(index as i32 % self.width, index as i32 / self.width)
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.tiles[index] == Tile::Free && !self.fruit[index] && self.segments_total[index] == 0
+    }
+
+    /// Applies `change` to the cell at `index` and keeps the free cell
+    /// counts in sync.
+    fn update(&mut self, index: usize, change: impl FnOnce(&mut Self)) {
+        let was_free = self.is_free(index);
+        change(self);
+        let row = index / self.width as usize;
+        match (was_free, self.is_free(index)) {
+            (true, false) => {
+                self.free_in_row[row] -= 1;
+                self.free_total -= 1;
+            }
+            (false, true) => {
+                self.free_in_row[row] += 1;
+                self.free_total += 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn tile(&self, cell: &Cell) -> Tile {
+        self.tiles[self.index(cell)]
+    }
+
+    pub fn has_fruit(&self, cell: &Cell) -> bool {
+        self.fruit[self.index(cell)]
+    }
+
+    /// Number of segments of the snake in `slot` that are in `cell`.
+    pub fn segments(&self, cell: &Cell, slot: usize) -> u16 {
+        self.segments[self.index(cell) * self.slots + slot]
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free_total
+    }
+
+    /// Free cells row by row, left to right.
+    pub fn free_cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..self.tiles.len())
+            .filter(|&index| self.is_free(index))
+            .map(|index| self.cell_at(index))
+    }
+
+    /// Picks a free cell with every free cell being equally likely. The
+    /// pick only depends on the board as it is now, so games with the same
+    /// board and RNG state pick the same cell however they got there.
+    pub fn random_free_cell(&self, rng: &mut impl Rng) -> Option<Cell> {
+        if self.free_total == 0 {
+            return None;
+        }
+
+        let mut nth = rng.random_range(0..self.free_total);
+        let row = self.free_in_row.iter().position(|&free| {
+            if nth < free {
+                true
+            } else {
+                nth -= free;
+                false
+            }
+        })?;
+
+        let row_start = row * self.width as usize;
+        (row_start..row_start + self.width as usize)
+            .filter(|&index| self.is_free(index))
+            .nth(nth)
+            .map(|index| self.cell_at(index))
+    }
+
+    pub fn set_tile(&mut self, cell: &Cell, tile: Tile) {
+        let index = self.index(cell);
+        self.update(index, |grid| grid.tiles[index] = tile);
+    }
+
+    pub fn add_segment(&mut self, cell: &Cell, slot: usize) {
+        let index = self.index(cell);
+        self.update(index, |grid| {
+            grid.segments[index * grid.slots + slot] += 1;
+            grid.segments_total[index] += 1;
+        });
+    }
+
+    pub fn remove_segment(&mut self, cell: &Cell, slot: usize) {
+        let index = self.index(cell);
+        self.update(index, |grid| {
+            grid.segments[index * grid.slots + slot] -= 1;
+            grid.segments_total[index] -= 1;
+        });
+    }
+
+    pub fn set_fruit(&mut self, cell: &Cell, has_fruit: bool) {
+        let index = self.index(cell);
+        self.update(index, |grid| grid.fruit[index] = has_fruit);
+    }
+}
@@ -0,0 +1,115 @@
+use macroquad::prelude as mcq;
+use serde::{Deserialize, Serialize};
+
+use crate::map::{Cell, Map, Zone};
+use crate::snake_cfg::{
+    SAFE_ZONE_MIN_HEIGHT, SAFE_ZONE_MIN_WIDTH, SAFE_ZONE_SHRINK_INTERVAL_SECS,
+    SAFE_ZONE_SHRINK_START_SECS, SAFE_ZONE_SHRINK_STEP, SNAKE_SIZE,
+};
+
+/// Playable part of the board in battle royale rounds. It starts as the
+/// whole board and shrinks towards the middle on a fixed schedule; whatever
+/// is outside counts as wall.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SafeZone {
+    pub current: Zone,
+    /// What the zone shrinks to next, `None` once it is as small as it gets.
+    pub next: Option<Zone>,
+    pub next_shrink_tick: u64,
+}
+
+impl SafeZone {
+    pub fn new(map: &Map, tick_rate: u64) -> Self {
+        let current = Zone {
+            x: 0,
+            y: 0,
+            width: map.width,
+            height: map.height,
+        };
+
+        Self {
+            current,
+            next: shrunk(&current),
+            next_shrink_tick: SAFE_ZONE_SHRINK_START_SECS * tick_rate,
+        }
+    }
+
+    /// Shrinks the zone if it is time to. Returns the cells that closed.
+    pub fn update(&mut self, tick: u64, tick_rate: u64) -> Vec<Cell> {
+        let Some(next) = self.next else {
+            return Vec::new();
+        };
+        if tick < self.next_shrink_tick {
+            return Vec::new();
+        }
+
+        let closed = self
+            .current
+            .cells()
+            .filter(|cell| !next.contains(cell))
+            .collect();
+
+        self.current = next;
+        self.next = shrunk(&next);
+        self.next_shrink_tick += SAFE_ZONE_SHRINK_INTERVAL_SECS * tick_rate;
+
+        closed
+    }
+
+    /// Cells of the board that are outside the zone.
+    pub fn closed_cells(&self, map: &Map) -> impl Iterator<Item = Cell> + '_ {
+        let board = Zone {
+            x: 0,
+            y: 0,
+            width: map.width,
+            height: map.height,
+        };
+        board
+            .cells()
+            .filter(move |cell| !self.current.contains(cell))
+    }
+
+    /// Shades everything outside the zone and outlines the next one.
+    pub fn draw(&self, map: &Map) {
+        let outside = mcq::Color::new(0.0, 0.0, 0.0, 0.8);
+        for cell in self.closed_cells(map) {
+            let pos = cell.to_pos();
+            mcq::draw_rectangle(pos.x, pos.y, SNAKE_SIZE, SNAKE_SIZE, outside);
+        }
+
+        draw_outline(&self.current, mcq::WHITE);
+        if let Some(next) = &self.next {
+            draw_outline(next, mcq::ORANGE);
+        }
+    }
+}
+
+/// The zone one shrink step later, kept centred and no smaller than the
+/// minimum size.
+fn shrunk(zone: &Zone) -> Option<Zone> {
+    let width = (zone.width - 2 * SAFE_ZONE_SHRINK_STEP).max(SAFE_ZONE_MIN_WIDTH.min(zone.width));
+    let height =
+        (zone.height - 2 * SAFE_ZONE_SHRINK_STEP).max(SAFE_ZONE_MIN_HEIGHT.min(zone.height));
+    if width == zone.width && height == zone.height {
+        return None;
+    }
+
+    Some(Zone {
+        x: zone.x + (zone.width - width) / 2,
+        y: zone.y + (zone.height - height) / 2,
+        width,
+        height,
+    })
+}
+
+fn draw_outline(zone: &Zone, color: mcq::Color) {
+    let corner = Cell::new(zone.x, zone.y).to_pos();
+    mcq::draw_rectangle_lines(
+        corner.x,
+        corner.y,
+        zone.width as f32 * SNAKE_SIZE,
+        zone.height as f32 * SNAKE_SIZE,
+        3.0,
+        color,
+    );
+}
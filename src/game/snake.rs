@@ -1,63 +1,140 @@
+use std::collections::VecDeque;
+
 use crate::common::{MyColor, MyVec2};
+use crate::map::{Cell, Map};
 use crate::{
     common::to_color,
-    snake_cfg::{SCREEN_HEIGHT, SCREEN_WIDTH, SNAKE_SIZE, SNAKE_TICKS_PER_MOVE},
+    snake_cfg::{
+        SNAKE_SIZE, SNAKE_TICKS_PER_MOVE_MAX, SNAKE_TICKS_PER_MOVE_MIN, SNAKE_TURN_QUEUE_LEN,
+    },
 };
 use serde::{Serialize, Deserialize};
 use macroquad::prelude::draw_rectangle;
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) enum Direction {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
     Up,
     Down,
     Left,
     Right,
 }
 
+impl Direction {
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    fn is_perpendicular(&self, other: Direction) -> bool {
+        match self {
+            Direction::Left | Direction::Right => {
+                matches!(other, Direction::Up | Direction::Down)
+            }
+            Direction::Up | Direction::Down => {
+                matches!(other, Direction::Left | Direction::Right)
+            }
+        }
+    }
+}
+
 pub enum SnakesColission {
     HeadToTailColission,
     HeadToHeadColission,
 }
 
+/// Decides whether a head may enter the cell that another snake's tail is
+/// leaving during the same step.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TailRule {
+    /// The cell is free as soon as the tail starts leaving it, so snakes can
+    /// closely follow each other.
+    Vacated,
+    /// The tail still occupies the cell for the whole step; entering it
+    /// counts as running into the other snake.
+    Blocked,
+}
+
+impl TailRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TailRule::Vacated => "tails vacate their cell",
+            TailRule::Blocked => "tails block their cell",
+        }
+    }
+}
+
+/// Temporary change of movement speed, e.g. after eating a speed fruit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SpeedEffect {
+    ticks_per_move_delta: i32,
+    ticks_left: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Snake {
+    /// Index of the snake in the occupancy grid.
+    slot: usize,
     positions: Vec<MyVec2>,
+    previous_head_position: MyVec2,
     previous_tail_position: MyVec2,
     direction: Direction,
+    /// Turns requested since the last step, applied one per step.
+    pending_turns: VecDeque<Direction>,
     color: MyColor,
     update_counter: u32,
+    ticks_per_move: u32,
+    speed_effect: Option<SpeedEffect>,
+    moved: bool,
 }
 
 impl Snake {
-    pub(crate) fn new(color: MyColor, pos: MyVec2) -> Self {
+    pub(crate) fn new(
+        slot: usize,
+        color: MyColor,
+        pos: MyVec2,
+        direction: Direction,
+        ticks_per_move: u32,
+        map: &Map,
+    ) -> Self {
         let last_tail_pos = pos;
 
         let mut ret = Self {
-            direction: Direction::Left,
+            slot,
+            direction,
+            pending_turns: VecDeque::new(),
+            previous_head_position: last_tail_pos,
             previous_tail_position: last_tail_pos,
             positions: Vec::from([last_tail_pos]),
-            color: color,
+            color,
             update_counter: 0,
+            ticks_per_move,
+            speed_effect: None,
+            moved: false,
         };
 
-        ret.move_step();
+        ret.move_step(map);
         ret.grow();
 
         ret
     }
 
-    fn get_head_pos(&self) -> MyVec2 {
+    pub(crate) fn get_head_pos(&self) -> MyVec2 {
         self.positions[0]
     }
 
-    fn get_tail(&self) -> &[MyVec2] {
-        return &self.positions[1..];
-    }
+    fn move_step(&mut self, map: &Map) {
+        if let Some(turn) = self.pending_turns.pop_front() {
+            self.direction = turn;
+        }
 
-    fn move_step(&mut self) {
         let tail_pos = self.positions.last_mut().unwrap();
         self.previous_tail_position = *tail_pos;
+        self.previous_head_position = self.positions[0];
 
         if self.positions.len() > 1 {
             for i in (1..self.positions.len()).rev() {
@@ -65,39 +142,85 @@ impl Snake {
             }
         }
 
-        let head_pos = &mut self.positions[0];
+        let head = Cell::from_pos(&self.positions[0]).step(self.direction);
+        self.positions[0] = map.wrap(head).to_pos();
+    }
 
-        match self.direction {
-            Direction::Up => head_pos.y -= SNAKE_SIZE,
-            Direction::Down => head_pos.y += SNAKE_SIZE,
-            Direction::Left => head_pos.x -= SNAKE_SIZE,
-            Direction::Right => head_pos.x += SNAKE_SIZE,
-        }
+    pub(crate) fn len(&self) -> usize {
+        self.positions.len()
+    }
 
-        if head_pos.x > SCREEN_WIDTH {
-            head_pos.x = 0.0;
-        } else if head_pos.x < 0.0 {
-            head_pos.x = SCREEN_WIDTH;
-        }
+    /// Whether the snake took a step during the last `move_step_tick`.
+    pub(crate) fn moved(&self) -> bool {
+        self.moved
+    }
 
-        if head_pos.y > SCREEN_HEIGHT {
-            head_pos.y = 0.0;
-        } else if head_pos.y < 0.0 {
-            head_pos.y = SCREEN_HEIGHT;
-        }
+    pub(crate) fn set_ticks_per_move(&mut self, ticks_per_move: u32) {
+        self.ticks_per_move = ticks_per_move;
+    }
+
+    /// Ticks between two steps, including any temporary speed effect.
+    fn effective_ticks_per_move(&self) -> u32 {
+        let delta = self
+            .speed_effect
+            .as_ref()
+            .map_or(0, |effect| effect.ticks_per_move_delta);
+
+        self.ticks_per_move
+            .saturating_add_signed(delta)
+            .clamp(SNAKE_TICKS_PER_MOVE_MIN, SNAKE_TICKS_PER_MOVE_MAX)
     }
 
-    pub(crate) fn move_step_tick(&mut self) {
+    pub(crate) fn move_step_tick(&mut self, map: &Map) {
+        let ticks_per_move = self.effective_ticks_per_move();
+        self.moved = false;
+
+        if let Some(effect) = &mut self.speed_effect {
+            effect.ticks_left -= 1;
+            if effect.ticks_left == 0 {
+                self.speed_effect = None;
+            }
+        }
+
         self.update_counter += 1;
-        if self.update_counter % SNAKE_TICKS_PER_MOVE as u32 != 0 {
+        if self.update_counter < ticks_per_move {
             return;
         }
 
-        self.move_step();
+        self.update_counter = 0;
+        self.move_step(map);
+        self.moved = true;
     }
 
     pub(crate) fn grow(&mut self) {
-        self.positions.push(self.previous_tail_position);
+        self.grow_by(1);
+    }
+
+    pub(crate) fn grow_by(&mut self, segments: u32) {
+        for _ in 0..segments {
+            self.positions.push(self.previous_tail_position);
+        }
+    }
+
+    /// Removes segments from the end of the tail and returns where they
+    /// were. Returns `None`, leaving the snake untouched, if it would be left
+    /// with less than one segment.
+    pub(crate) fn shrink(&mut self, segments: u32) -> Option<Vec<MyVec2>> {
+        if self.positions.len() <= segments as usize {
+            return None;
+        }
+
+        Some(
+            self.positions
+                .split_off(self.positions.len() - segments as usize),
+        )
+    }
+
+    pub(crate) fn set_speed_effect(&mut self, ticks_per_move_delta: i32, duration_ticks: u32) {
+        self.speed_effect = Some(SpeedEffect {
+            ticks_per_move_delta,
+            ticks_left: duration_ticks,
+        });
     }
 
     pub(crate) fn draw(&self) {
@@ -106,52 +229,170 @@ impl Snake {
         }
     }
 
+    /// Queues a turn. It is checked against the direction the snake will
+    /// be heading in once the turns queued before it are applied, so a
+    /// quick double turn can never reverse the snake into its own neck.
     pub(crate) fn change_direction(&mut self, direction: Direction) {
-        match self.direction {
-            Direction::Left | Direction::Right => match direction {
-                Direction::Down | Direction::Up => self.direction = direction,
-                _ => {}
-            },
-            Direction::Up | Direction::Down => match direction {
-                Direction::Left | Direction::Right => self.direction = direction,
-                _ => {}
-            },
+        if self.pending_turns.len() >= SNAKE_TURN_QUEUE_LEN {
+            return;
+        }
+
+        let heading = self.pending_turns.back().copied().unwrap_or(self.direction);
+        if heading.is_perpendicular(direction) {
+            self.pending_turns.push_back(direction);
         }
     }
 
-    fn collides_head(&self, object: &MyVec2) -> bool {
-        return self.get_head_pos() == *object;
+    pub(crate) fn slot(&self) -> usize {
+        self.slot
     }
 
-    fn collides_tail(&self, object: &MyVec2) -> bool {
-        for pos in self.get_tail() {
-            if *pos == *object {
-                return true;
-            }
-        }
-        false
+    pub(crate) fn color(&self) -> MyColor {
+        self.color
     }
 
-    pub(crate) fn collides_self(&self) -> bool {
-        let head_pos = self.get_head_pos();
-        self.collides_tail(&head_pos)
+    pub(crate) fn positions(&self) -> &[MyVec2] {
+        &self.positions
+    }
+
+    pub(crate) fn previous_tail_pos(&self) -> MyVec2 {
+        self.previous_tail_position
     }
 
-    pub(crate) fn collides_other(&self, other: &Snake) -> Option<SnakesColission> {
+    /// Checks whether this snake's last step ran into `other`, taking into
+    /// account that `other` may have moved during the same step.
+    /// `other_in_head_cell` tells whether any segment of `other` is in the
+    /// cell this snake's head is in.
+    pub(crate) fn collides_other(
+        &self,
+        other: &Snake,
+        other_in_head_cell: bool,
+        tail_rule: TailRule,
+    ) -> Option<SnakesColission> {
         let head_pos = self.get_head_pos();
 
-        if other.collides_head(&head_pos) {
+        if other_in_head_cell && other.get_head_pos() == head_pos {
+            // with per-snake speeds only one of the snakes may have stepped;
+            // running into a head that stood still is the mover's fault alone
+            if other.moved {
+                return Some(SnakesColission::HeadToHeadColission);
+            }
+            return Some(SnakesColission::HeadToTailColission);
+        }
+
+        // heads moving towards each other from adjacent cells swap places
+        // and would otherwise pass straight through each other
+        if other.moved
+            && head_pos == other.previous_head_position
+            && other.get_head_pos() == self.previous_head_position
+        {
             return Some(SnakesColission::HeadToHeadColission);
         }
 
-        if other.collides_tail(&head_pos) {
+        if other_in_head_cell {
+            return Some(SnakesColission::HeadToTailColission);
+        }
+
+        if tail_rule == TailRule::Blocked && other.moved && head_pos == other.previous_tail_position
+        {
             return Some(SnakesColission::HeadToTailColission);
         }
 
         None
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOW: u32 = SNAKE_TICKS_PER_MOVE_MIN * 2;
+
+    fn snake(slot: usize, x: i32, direction: Direction, ticks_per_move: u32, map: &Map) -> Snake {
+        let color = MyColor {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 1.0,
+        };
+        Snake::new(
+            slot,
+            color,
+            Cell::new(x, 5).to_pos(),
+            direction,
+            ticks_per_move,
+            map,
+        )
+    }
+
+    /// Runs the ticks it takes the fastest snake to step once.
+    fn step(snakes: &mut [&mut Snake], map: &Map) {
+        for _ in 0..SNAKE_TICKS_PER_MOVE_MIN {
+            for snake in snakes.iter_mut() {
+                snake.move_step_tick(map);
+            }
+        }
+    }
+
+    fn collides(snake: &Snake, other: &Snake, tail_rule: TailRule) -> Option<SnakesColission> {
+        let other_in_head_cell = other.positions().contains(&snake.get_head_pos());
+        snake.collides_other(other, other_in_head_cell, tail_rule)
+    }
 
-    pub(crate) fn collides_object(&self, object: &MyVec2) -> bool {
-        self.collides_head(object) || self.collides_tail(object)
+    #[test]
+    fn swapping_heads_collide_under_both_rules() {
+        let map = Map::empty(20, 20);
+        for tail_rule in [TailRule::Vacated, TailRule::Blocked] {
+            // heads at x = 5 and x = 6, facing each other
+            let mut left = snake(0, 4, Direction::Right, SNAKE_TICKS_PER_MOVE_MIN, &map);
+            let mut right = snake(1, 7, Direction::Left, SNAKE_TICKS_PER_MOVE_MIN, &map);
+            step(&mut [&mut left, &mut right], &map);
+
+            assert!(matches!(
+                collides(&left, &right, tail_rule),
+                Some(SnakesColission::HeadToHeadColission)
+            ));
+            assert!(matches!(
+                collides(&right, &left, tail_rule),
+                Some(SnakesColission::HeadToHeadColission)
+            ));
+        }
+    }
+
+    #[test]
+    fn entering_a_vacated_tail_cell_depends_on_the_rule() {
+        let map = Map::empty(20, 20);
+        for (tail_rule, blocked) in [(TailRule::Vacated, false), (TailRule::Blocked, true)] {
+            // the follower's head steps into the cell the leader's tail leaves
+            let mut follower = snake(0, 3, Direction::Right, SNAKE_TICKS_PER_MOVE_MIN, &map);
+            let mut leader = snake(1, 5, Direction::Right, SNAKE_TICKS_PER_MOVE_MIN, &map);
+            step(&mut [&mut follower, &mut leader], &map);
+
+            assert_eq!(follower.get_head_pos(), leader.previous_tail_pos());
+            assert_eq!(
+                matches!(
+                    collides(&follower, &leader, tail_rule),
+                    Some(SnakesColission::HeadToTailColission)
+                ),
+                blocked
+            );
+            assert!(collides(&leader, &follower, tail_rule).is_none());
+        }
+    }
+
+    #[test]
+    fn entering_a_tail_that_did_not_move_collides_under_both_rules() {
+        let map = Map::empty(20, 20);
+        for tail_rule in [TailRule::Vacated, TailRule::Blocked] {
+            let mut follower = snake(0, 3, Direction::Right, SNAKE_TICKS_PER_MOVE_MIN, &map);
+            let mut leader = snake(1, 5, Direction::Right, SLOW, &map);
+            step(&mut [&mut follower, &mut leader], &map);
+
+            assert!(follower.moved() && !leader.moved());
+            assert!(matches!(
+                collides(&follower, &leader, tail_rule),
+                Some(SnakesColission::HeadToTailColission)
+            ));
+        }
     }
 }
@@ -2,6 +2,7 @@
 use serde::{Serialize, Deserialize};
 
 use crate::game::game_core::GameCore;
+use crate::game::game_event::GameEvent;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message
@@ -9,6 +10,12 @@ pub enum Message
     JoinLobby{player_name: String},
     Ok,
     Nok{error_msg: String},
-    GameUpdate(GameCore),
+    GameUpdate(Box<GameCore>),
+    /// Events since the previous update.
+    GameEvents(Vec<GameEvent>),
+    /// Hash of the state in the update that follows.
+    StateHash{match_tick: u64, hash: u64},
     SendInput(char),
+    /// Sent instead of `Ok` on joining a server that has a message of the day.
+    Welcome{motd: String},
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use macroquad::color::{BLACK, Color, DARKBROWN, ORANGE, RED, WHITE};
+
+use crate::common::to_color;
+use crate::game::game_core::GameCore;
+use crate::game::map::{Cell, Zone};
+
+/// Board drawn in software, one RGB triple per pixel, for exporting frames
+/// where there is no window to draw to.
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+    /// Size of a board cell, in pixels.
+    cell_size: usize,
+}
+
+impl Canvas {
+    pub fn new(board_width: i32, board_height: i32, cell_size: usize) -> Self {
+        let width = board_width.max(0) as usize * cell_size;
+        let height = board_height.max(0) as usize * cell_size;
+        Self {
+            width,
+            height,
+            pixels: vec![[0; 3]; width * height],
+            cell_size,
+        }
+    }
+
+    /// Draws the board the way the game window shows it while playing,
+    /// with the cells outlined.
+    pub fn draw_game(&mut self, game: &GameCore) {
+        self.fill(RED);
+        let grid = Color::new(0.0, 0.0, 0.0, 0.15);
+        for x in 0..game.map().width {
+            for y in 0..game.map().height {
+                self.outline_cell(Cell::new(x, y), grid);
+            }
+        }
+
+        for cell in &game.map().walls {
+            self.fill_cell(*cell, BLACK);
+        }
+        for cell in &game.map().obstacles {
+            self.fill_cell(*cell, DARKBROWN);
+        }
+
+        for (color, positions) in game.snake_bodies() {
+            for pos in positions {
+                self.fill_cell(Cell::from_pos(pos), to_color(color));
+            }
+        }
+
+        for fruit in &game.fruits {
+            self.fill_cell(Cell::from_pos(&fruit.pos), fruit.kind.info().color);
+        }
+
+        if let Some(safe_zone) = &game.safe_zone {
+            for cell in safe_zone.closed_cells(game.map()) {
+                self.fill_cell(cell, Color::new(0.0, 0.0, 0.0, 0.8));
+            }
+            self.outline_zone(&safe_zone.current, WHITE);
+            if let Some(next) = &safe_zone.next {
+                self.outline_zone(next, ORANGE);
+            }
+        }
+    }
+
+    fn fill(&mut self, color: Color) {
+        self.pixels.fill(to_rgb(color));
+    }
+
+    fn fill_cell(&mut self, cell: Cell, color: Color) {
+        let size = self.cell_size as i32;
+        self.fill_rect(cell.x * size, cell.y * size, size, size, color);
+    }
+
+    fn outline_cell(&mut self, cell: Cell, color: Color) {
+        let size = self.cell_size as i32;
+        let (x, y) = (cell.x * size, cell.y * size);
+        self.fill_rect(x, y, size, 1, color);
+        self.fill_rect(x, y + 1, 1, size - 1, color);
+    }
+
+    fn outline_zone(&mut self, zone: &Zone, color: Color) {
+        let size = self.cell_size as i32;
+        let (x, y) = (zone.x * size, zone.y * size);
+        let (width, height) = (zone.width * size, zone.height * size);
+        let thickness = (size / 6).max(1);
+        self.fill_rect(x, y, width, thickness, color);
+        self.fill_rect(x, y + height - thickness, width, thickness, color);
+        self.fill_rect(x, y, thickness, height, color);
+        self.fill_rect(x + width - thickness, y, thickness, height, color);
+    }
+
+    /// Blends `color` over a rectangle, clipped to the canvas.
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        let x_range = x.max(0) as usize..((x + width).max(0) as usize).min(self.width);
+        let y_range = y.max(0) as usize..((y + height).max(0) as usize).min(self.height);
+
+        for row in y_range {
+            for pixel in &mut self.pixels[row * self.width..][x_range.clone()] {
+                *pixel = blend(*pixel, color);
+            }
+        }
+    }
+}
+
+fn to_rgb(color: Color) -> [u8; 3] {
+    [color.r, color.g, color.b].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn blend(below: [u8; 3], color: Color) -> [u8; 3] {
+    let above = to_rgb(color);
+    let alpha = color.a.clamp(0.0, 1.0);
+    let mut blended = [0; 3];
+    for channel in 0..3 {
+        blended[channel] =
+            (above[channel] as f32 * alpha + below[channel] as f32 * (1.0 - alpha)).round() as u8;
+    }
+    blended
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Keys tracked before the ones whose window ran out are dropped.
+const TRACKED_KEYS_MAX: usize = 1024;
+
+/// Counts events per key, e.g. per player, in fixed windows of time and
+/// refuses the ones over the limit. A limit of 0 lets everything through.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// Start of the current window and the events counted in it, per key.
+    windows: HashMap<String, (Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Takes effect for the events counted from now on.
+    pub fn set_limit(&mut self, limit: u32) {
+        self.limit = limit;
+    }
+
+    /// Counts an event for `key` and tells whether it is within the limit.
+    pub fn allow(&mut self, key: &str) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        if self.windows.len() >= TRACKED_KEYS_MAX && !self.windows.contains_key(key) {
+            let window = self.window;
+            self.windows
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = self.windows.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::game_core::{
+    ArenaSource, ENTER, FinishDetails, GameCore, GameSettings, GameState, MatchState,
+};
+use crate::game::map::Map;
+
+/// Bumped whenever the file layout or the simulation changes in a way that
+/// makes older replays play out differently.
+pub const REPLAY_VERSION: u32 = 6;
+
+const PREFIX_SIZE: usize = 4;
+
+/// Everything needed to set up a match the way it started.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+    pub settings: GameSettings,
+    pub arena: ArenaSource,
+    /// Board used when the arena isn't generated.
+    pub map: Map,
+    pub players: Vec<String>,
+}
+
+/// Something a player did to the game.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReplayInput {
+    Key(char),
+    Left,
+}
+
+/// Input applied once the game reached `match_tick`, before its next update.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedInput {
+    pub match_tick: u64,
+    pub player: String,
+    pub input: ReplayInput,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedResult {
+    pub match_tick: u64,
+    pub details: FinishDetails,
+    pub match_state: MatchState,
+}
+
+/// One entry of a replay file. A file is a header, the inputs in the order
+/// they were applied and, if the match ran to the end, its result.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReplayRecord {
+    Header(ReplayHeader),
+    Input(RecordedInput),
+    Result(RecordedResult),
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    InvalidData(String),
+    UnsupportedVersion(u32),
+}
+
+/// A replay file as read back.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub inputs: Vec<RecordedInput>,
+    /// Missing if the server stopped before the match was over.
+    pub result: Option<RecordedResult>,
+}
+
+impl Replay {
+    /// Reads a replay. A file cut short, as left by a crash, loads up to its
+    /// last complete record.
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let data = fs::read(path).map_err(ReplayError::Io)?;
+
+        let mut records = Vec::new();
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            match read_record(rest) {
+                Some((record, remainder)) => {
+                    records.push(record);
+                    rest = remainder;
+                }
+                None => {
+                    eprintln!(
+                        "[WARNING]: replay {} is cut short after {} records",
+                        path.display(),
+                        records.len()
+                    );
+                    break;
+                }
+            }
+        }
+
+        let mut records = records.into_iter();
+        let header = match records.next() {
+            Some(ReplayRecord::Header(header)) => header,
+            _ => Err(ReplayError::InvalidData("missing header".to_string()))?,
+        };
+        if header.version != REPLAY_VERSION {
+            Err(ReplayError::UnsupportedVersion(header.version))?
+        }
+
+        let mut inputs = Vec::new();
+        let mut result = None;
+        for record in records {
+            match record {
+                ReplayRecord::Input(input) => inputs.push(input),
+                ReplayRecord::Result(recorded) => result = Some(recorded),
+                ReplayRecord::Header(_) => {
+                    Err(ReplayError::InvalidData("more than one header".to_string()))?
+                }
+            }
+        }
+
+        Ok(Self {
+            header,
+            inputs,
+            result,
+        })
+    }
+
+    /// Last tick anything was recorded at.
+    pub fn last_tick(&self) -> u64 {
+        match &self.result {
+            Some(result) => result.match_tick,
+            None => self.inputs.last().map_or(0, |input| input.match_tick),
+        }
+    }
+}
+
+/// Splits the first length prefixed record off `data`, if it is complete.
+fn read_record(data: &[u8]) -> Option<(ReplayRecord, &[u8])> {
+    let prefix = data.get(..PREFIX_SIZE)?;
+    let len = u32::from_be_bytes(prefix.try_into().ok()?) as usize;
+    let frame = data.get(PREFIX_SIZE..PREFIX_SIZE + len)?;
+    let record = postcard::from_bytes(frame).ok()?;
+    Some((record, &data[PREFIX_SIZE + len..]))
+}
+
+/// Re-simulates a replay. The game is passed in on every call so the caller
+/// can keep it wherever it is drawn from.
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    /// Index of the next input to apply.
+    next_input: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            next_input: 0,
+        }
+    }
+
+    /// The game as the match started, with the inputs of its first tick
+    /// applied.
+    pub fn restart(&mut self) -> GameCore {
+        let header = &self.replay.header;
+        let mut game = GameCore::with_seed(true, header.seed);
+        game.set_map(header.map.clone());
+        game.set_settings(header.settings.clone());
+        game.set_arena(header.arena);
+        for player in &header.players {
+            game.add_player(player);
+            game.handle_input(player, ENTER);
+        }
+        game.start_match(header.seed);
+        game.begin_countdown();
+
+        self.next_input = 0;
+        self.apply_inputs(&mut game);
+        game
+    }
+
+    pub fn is_at_end(&self, game: &GameCore) -> bool {
+        game.match_tick >= self.replay.last_tick()
+    }
+
+    /// Advances the game by one update.
+    pub fn step(&mut self, game: &mut GameCore) {
+        if self.is_at_end(game) {
+            return;
+        }
+        game.update();
+        self.apply_inputs(game);
+
+        if self.is_at_end(game)
+            && let Some(result) = &self.replay.result
+        {
+            let same_result = matches!(&game.state, GameState::Finished(details)
+                if details.winners() == result.details.winners());
+            if !same_result {
+                eprintln!(
+                    "[WARNING]: replay ended in {:?} instead of the recorded result",
+                    game.state
+                );
+            }
+        }
+    }
+
+    /// Moves the game to `match_tick`, starting over if it is in the past.
+    pub fn seek(&mut self, game: &mut GameCore, match_tick: u64) {
+        if match_tick < game.match_tick {
+            *game = self.restart();
+        }
+        while game.match_tick < match_tick && !self.is_at_end(game) {
+            self.step(game);
+        }
+    }
+
+    fn apply_inputs(&mut self, game: &mut GameCore) {
+        while let Some(recorded) = self.replay.inputs.get(self.next_input)
+            && recorded.match_tick <= game.match_tick
+        {
+            match recorded.input {
+                ReplayInput::Key(c) => game.handle_input(&recorded.player, c),
+                ReplayInput::Left => game.remove_player(&recorded.player),
+            }
+            self.next_input += 1;
+        }
+    }
+}
+
+/// Writes the replay of one match as it is played. Every record goes
+/// straight to the file, length prefixed, so a crash leaves a usable prefix.
+pub struct ReplayRecorder {
+    file: File,
+    pub path: PathBuf,
+}
+
+impl ReplayRecorder {
+    /// Starts recording the match `game` has just started.
+    pub fn create(dir: &Path, game: &GameCore) -> Result<Self, ReplayError> {
+        fs::create_dir_all(dir).map_err(ReplayError::Io)?;
+        let path = dir.join(format!("match_{}.replay", game.seed));
+        let file = File::create(&path).map_err(ReplayError::Io)?;
+
+        let mut recorder = Self { file, path };
+        recorder.write(&ReplayRecord::Header(ReplayHeader {
+            version: REPLAY_VERSION,
+            seed: game.seed,
+            settings: game.settings.clone(),
+            arena: game.arena,
+            map: game.loaded_map().clone(),
+            players: game.players.keys().cloned().collect(),
+        }))?;
+        Ok(recorder)
+    }
+
+    pub fn record_input(
+        &mut self,
+        game: &GameCore,
+        player: &str,
+        input: ReplayInput,
+    ) -> Result<(), ReplayError> {
+        self.write(&ReplayRecord::Input(RecordedInput {
+            match_tick: game.match_tick,
+            player: player.to_string(),
+            input,
+        }))
+    }
+
+    /// Records the result of the match, which `game` must have finished.
+    pub fn record_result(
+        &mut self,
+        game: &GameCore,
+        details: &FinishDetails,
+    ) -> Result<(), ReplayError> {
+        self.write(&ReplayRecord::Result(RecordedResult {
+            match_tick: game.match_tick,
+            details: details.clone(),
+            match_state: game.match_state.clone(),
+        }))
+    }
+
+    fn write(&mut self, record: &ReplayRecord) -> Result<(), ReplayError> {
+        // room for the length prefix, filled in once the size is known
+        let mut frame = postcard::to_extend(record, vec![0; PREFIX_SIZE])
+            .map_err(|err| ReplayError::InvalidData(err.to_string()))?;
+        let len = (frame.len() - PREFIX_SIZE) as u32;
+        frame[..PREFIX_SIZE].copy_from_slice(&len.to_be_bytes());
+
+        self.file.write_all(&frame).map_err(ReplayError::Io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays a match the way the server does, recording it: both players
+    /// steer around the board until the match ends, or one of them leaves.
+    fn record_match(dir: &Path) -> (GameCore, PathBuf) {
+        let mut game = GameCore::with_seed(true, 11);
+        game.set_map(Map::empty(30, 20));
+        game.settings.countdown_secs = 0;
+        for name in ["a", "b"] {
+            game.add_player(name);
+            game.handle_input(name, ENTER);
+        }
+        game.update();
+        let mut recorder = ReplayRecorder::create(dir, &game).unwrap();
+
+        let turns = ['d', 's', 'a', 'w'];
+        for step in 0..3000 {
+            for (player, period) in [("a", 500), ("b", 700)] {
+                if step % period == 0 {
+                    let turn = turns[step / period % turns.len()];
+                    game.handle_input(player, turn);
+                    recorder
+                        .record_input(&game, player, ReplayInput::Key(turn))
+                        .unwrap();
+                }
+            }
+            game.update();
+            if matches!(game.state, GameState::Finished(_)) {
+                break;
+            }
+        }
+
+        if !matches!(game.state, GameState::Finished(_)) {
+            game.remove_player("b");
+            recorder
+                .record_input(&game, "b", ReplayInput::Left)
+                .unwrap();
+        }
+        let GameState::Finished(details) = &game.state else {
+            panic!("the match never finished");
+        };
+        recorder.record_result(&game, details).unwrap();
+        (game, recorder.path)
+    }
+
+    #[test]
+    fn replayed_match_ends_like_the_recorded_one() {
+        let dir = std::env::temp_dir().join(format!("snake_replay_test_{}", std::process::id()));
+        let (game, path) = record_match(&dir);
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(replay.result.is_some());
+
+        let mut player = ReplayPlayer::new(replay);
+        let mut replayed = player.restart();
+        player.seek(&mut replayed, u64::MAX);
+
+        assert_eq!(replayed.match_tick, game.match_tick);
+        assert_eq!(replayed.state_hash(), game.state_hash());
+        let (GameState::Finished(recorded), GameState::Finished(details)) =
+            (&game.state, &replayed.state)
+        else {
+            panic!("replay ended in {:?}", replayed.state);
+        };
+        assert_eq!(details.winners(), recorded.winners());
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use macroquad::prelude as mcq;
+
+use crate::game::GameLocal;
+use crate::game::game_core::{ENTER, ESCAPE};
+use crate::replay::{Replay, ReplayError, ReplayPlayer};
+
+const SPEED_MIN: f64 = 0.25;
+const SPEED_MAX: f64 = 8.0;
+
+/// Plays a replay back for watching, with no player of our own.
+pub struct ReplayViewer {
+    player: ReplayPlayer,
+    game: GameLocal,
+    paused: bool,
+    speed: f64,
+    /// Ticks due but not simulated yet, as playback rarely lines up with
+    /// frames.
+    pending_ticks: f64,
+    /// Tick typed in to seek to.
+    seek_text: String,
+}
+
+impl ReplayViewer {
+    pub fn open(path: &Path) -> Result<Self, ReplayError> {
+        let mut player = ReplayPlayer::new(Replay::load(path)?);
+        let game = GameLocal::spectator(player.restart());
+
+        let mut viewer = Self {
+            player,
+            game,
+            paused: false,
+            speed: 1.0,
+            pending_ticks: 0.0,
+            seek_text: String::new(),
+        };
+        viewer.forward_events();
+        Ok(viewer)
+    }
+
+    pub fn handle_input(&mut self, c: char) {
+        match c {
+            ' ' => {
+                if self.is_at_end() {
+                    self.seek(0);
+                }
+                self.paused = !self.paused;
+            }
+            '+' | '=' => self.speed = (self.speed * 2.0).min(SPEED_MAX),
+            '-' => self.speed = (self.speed / 2.0).max(SPEED_MIN),
+            '.' => {
+                self.paused = true;
+                self.step();
+            }
+            '0'..='9' => self.seek_text.push(c),
+            ENTER => {
+                if let Ok(match_tick) = self.seek_text.parse() {
+                    self.seek(match_tick);
+                }
+                self.seek_text.clear();
+            }
+            ESCAPE => self.seek_text.clear(),
+            _ => {}
+        }
+    }
+
+    /// Plays on by however many ticks `elapsed` seconds are worth.
+    pub fn update(&mut self, elapsed: f64) {
+        if self.paused {
+            return;
+        }
+
+        self.pending_ticks += elapsed * self.speed * self.game.game_core.settings.tick_rate as f64;
+        while self.pending_ticks >= 1.0 {
+            if self.is_at_end() {
+                self.paused = true;
+                self.pending_ticks = 0.0;
+                return;
+            }
+            self.step();
+            self.pending_ticks -= 1.0;
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.player.is_at_end(&self.game.game_core)
+    }
+
+    fn step(&mut self) {
+        self.player.step(&mut self.game.game_core);
+        self.forward_events();
+    }
+
+    fn seek(&mut self, match_tick: u64) {
+        self.player.seek(&mut self.game.game_core, match_tick);
+        self.pending_ticks = 0.0;
+        self.forward_events();
+    }
+
+    fn forward_events(&mut self) {
+        let events = self.game.game_core.drain_events();
+        self.game.handle_events(events);
+    }
+
+    pub fn get_status_text(&self) -> String {
+        let state = if self.paused { ", paused" } else { "" };
+        let seek = if self.seek_text.is_empty() {
+            String::new()
+        } else {
+            format!(", seek to {}_", self.seek_text)
+        };
+        format!(
+            "replay tick {}/{}, {}x{}{}\n[space] play/pause, [+/-] speed, [.] step, [0-9 enter] seek",
+            self.game.game_core.match_tick,
+            self.player.replay.last_tick(),
+            self.speed,
+            state,
+            seek
+        )
+    }
+
+    pub fn draw(&self) {
+        self.game.draw();
+        mcq::draw_multiline_text(
+            self.get_status_text(),
+            mcq::screen_width() / 2.0 - 40.0,
+            mcq::screen_height() - 40.0,
+            20.0,
+            None,
+            mcq::WHITE,
+        );
+    }
+}
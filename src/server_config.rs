@@ -0,0 +1,495 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+
+use crate::game::game_core::GameSettings;
+use crate::game::game_mode::GameModeKind;
+use crate::snake_cfg::*;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    InvalidFormat(String),
+    InvalidArgument(String),
+    InvalidValue(String),
+}
+
+/// Settings as written in the config file or given on the command line.
+/// Anything left out falls back to the file, then to the built-in default.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigOverrides {
+    pub bind_address: Option<String>,
+    pub port: Option<u16>,
+    pub tick_rate: Option<u64>,
+    pub max_players: Option<usize>,
+    pub default_mode: Option<String>,
+    pub default_ticks_per_move: Option<u32>,
+    pub maps_dir: Option<PathBuf>,
+    pub map: Option<String>,
+    pub motd: Option<String>,
+    pub banned: Option<Vec<String>>,
+    pub input_rate_limit: Option<u32>,
+    pub join_rate_limit: Option<u32>,
+}
+
+impl ConfigOverrides {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let json =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        serde_json::from_str(&json).map_err(|err| ConfigError::InvalidFormat(err.to_string()))
+    }
+
+    /// Sets the value of a command-line option, given without its dashes.
+    pub fn set_option(&mut self, option: &str, value: &str) -> Result<(), ConfigError> {
+        match option {
+            "bind" => self.bind_address = Some(value.to_string()),
+            "port" => self.port = Some(parse_value(option, value)?),
+            "tick-rate" => self.tick_rate = Some(parse_value(option, value)?),
+            "max-players" => self.max_players = Some(parse_value(option, value)?),
+            "mode" => self.default_mode = Some(value.to_string()),
+            "ticks-per-move" => self.default_ticks_per_move = Some(parse_value(option, value)?),
+            "maps" => self.maps_dir = Some(PathBuf::from(value)),
+            "map" => self.map = Some(value.to_string()),
+            "motd" => self.motd = Some(value.to_string()),
+            _ => Err(ConfigError::InvalidArgument(format!(
+                "unknown option --{}",
+                option
+            )))?,
+        }
+        Ok(())
+    }
+
+    /// Values set here win over the ones in `base`.
+    fn or(self, base: Self) -> Self {
+        Self {
+            bind_address: self.bind_address.or(base.bind_address),
+            port: self.port.or(base.port),
+            tick_rate: self.tick_rate.or(base.tick_rate),
+            max_players: self.max_players.or(base.max_players),
+            default_mode: self.default_mode.or(base.default_mode),
+            default_ticks_per_move: self.default_ticks_per_move.or(base.default_ticks_per_move),
+            maps_dir: self.maps_dir.or(base.maps_dir),
+            map: self.map.or(base.map),
+            motd: self.motd.or(base.motd),
+            banned: self.banned.or(base.banned),
+            input_rate_limit: self.input_rate_limit.or(base.input_rate_limit),
+            join_rate_limit: self.join_rate_limit.or(base.join_rate_limit),
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| {
+        ConfigError::InvalidArgument(format!("--{} expects a number, got '{}'", option, value))
+    })
+}
+
+/// Where the config comes from, kept to read it again when it changes.
+pub struct ConfigSource {
+    path: PathBuf,
+    /// Whether the file was named on the command line, so has to exist.
+    required: bool,
+    /// Command-line options, which keep winning over the file on reload.
+    overrides: ConfigOverrides,
+    /// Modification time of the file as last read.
+    modified: Option<SystemTime>,
+}
+
+impl ConfigSource {
+    /// Without a path the default config file is used, if there is one.
+    pub fn new(path: Option<PathBuf>, overrides: ConfigOverrides) -> Self {
+        let required = path.is_some();
+        Self {
+            path: path.unwrap_or_else(|| PathBuf::from(SERVER_CONFIG_PATH)),
+            required,
+            overrides,
+            modified: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn file_modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Whether the file was written, created or removed since it was last
+    /// read.
+    pub fn has_changed(&self) -> bool {
+        self.file_modified() != self.modified
+    }
+
+    /// Reads the config file, if any, and applies the command-line options
+    /// on top.
+    pub fn load(&mut self) -> Result<ServerConfig, ConfigError> {
+        self.modified = self.file_modified();
+
+        let file = if self.required || self.path.exists() {
+            ConfigOverrides::from_file(&self.path)?
+        } else {
+            ConfigOverrides::default()
+        };
+        ServerConfig::resolve(self.overrides.clone().or(file))
+    }
+}
+
+/// Settings the server runs with. Only the defaults for new matches, the
+/// MOTD, the ban list and the rate limits can change while it runs; the rest
+/// is fixed at startup.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    /// Game updates per second. Clients learn it from the game settings.
+    /// Rule durations are given in seconds, but snake speeds are counted in
+    /// ticks, so any other rate changes how fast snakes move.
+    pub tick_rate: u64,
+    /// Players a match is played with, up to one per player colour.
+    pub max_players: usize,
+    pub default_mode: GameModeKind,
+    /// Ticks a snake waits between two steps, until changed in the lobby.
+    pub default_ticks_per_move: u32,
+    pub maps_dir: PathBuf,
+    /// Name of the map to play on. The first map in `maps_dir` is used when
+    /// it isn't set.
+    pub map: Option<String>,
+    /// Message shown to players as they join.
+    pub motd: Option<String>,
+    /// Nicknames and IP addresses that may not join.
+    pub banned: Vec<String>,
+    /// Input bursts a player may send per second, 0 for no limit. A burst
+    /// is whatever arrives in one tick, up to `SERVER_INPUT_BURST_MAX` inputs.
+    pub input_rate_limit: u32,
+    /// Join attempts allowed per minute from one IP address, 0 for no limit.
+    pub join_rate_limit: u32,
+}
+
+impl ServerConfig {
+    pub fn resolve(values: ConfigOverrides) -> Result<Self, ConfigError> {
+        // without maps the server falls back to an empty board, which is
+        // fine unless a directory was asked for
+        if let Some(dir) = &values.maps_dir
+            && !dir.is_dir()
+        {
+            Err(ConfigError::InvalidValue(format!(
+                "maps_dir {} is not a directory",
+                dir.display()
+            )))?
+        }
+
+        let config = Self {
+            bind_address: values
+                .bind_address
+                .unwrap_or_else(|| SERVER_BIND_ADDRESS.to_string()),
+            port: values.port.unwrap_or(SERVER_PORT),
+            tick_rate: values.tick_rate.unwrap_or(TICK_RATE_FREQ),
+            max_players: values.max_players.unwrap_or(PLAYER_COUNT_DEFAULT),
+            default_mode: match values.default_mode {
+                Some(name) => GameModeKind::from_name(&name).ok_or_else(|| {
+                    ConfigError::InvalidValue(format!(
+                        "default_mode '{}' is not one of classic, battle-royale, light-cycle",
+                        name
+                    ))
+                })?,
+                None => GameModeKind::Classic,
+            },
+            default_ticks_per_move: values
+                .default_ticks_per_move
+                .unwrap_or(SNAKE_TICKS_PER_MOVE as u32),
+            maps_dir: values.maps_dir.unwrap_or_else(|| PathBuf::from(MAPS_DIR)),
+            map: values.map,
+            motd: values.motd.filter(|motd| !motd.trim().is_empty()),
+            banned: values.banned.unwrap_or_default(),
+            input_rate_limit: values.input_rate_limit.unwrap_or(SERVER_INPUT_RATE_LIMIT),
+            join_rate_limit: values.join_rate_limit.unwrap_or(SERVER_JOIN_RATE_LIMIT),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind_address.is_empty() {
+            Err(ConfigError::InvalidValue(
+                "bind_address must not be empty".to_string(),
+            ))?
+        }
+        if self.port == 0 {
+            Err(ConfigError::InvalidValue("port must not be 0".to_string()))?
+        }
+        if !(SERVER_TICK_RATE_MIN..=SERVER_TICK_RATE_MAX).contains(&self.tick_rate) {
+            Err(ConfigError::InvalidValue(format!(
+                "tick_rate {} is outside {}..={}",
+                self.tick_rate, SERVER_TICK_RATE_MIN, SERVER_TICK_RATE_MAX
+            )))?
+        }
+        if !(2..=PLAYER_COUNT_MAX).contains(&self.max_players) {
+            Err(ConfigError::InvalidValue(format!(
+                "max_players {} is outside 2..={}, the number of player colours",
+                self.max_players, PLAYER_COUNT_MAX
+            )))?
+        }
+        if !(SNAKE_TICKS_PER_MOVE_MIN..=SNAKE_TICKS_PER_MOVE_MAX)
+            .contains(&self.default_ticks_per_move)
+        {
+            Err(ConfigError::InvalidValue(format!(
+                "default_ticks_per_move {} is outside {}..={}",
+                self.default_ticks_per_move, SNAKE_TICKS_PER_MOVE_MIN, SNAKE_TICKS_PER_MOVE_MAX
+            )))?
+        }
+        if let Some(motd) = &self.motd
+            && motd.chars().count() > SERVER_MOTD_LEN_MAX
+        {
+            Err(ConfigError::InvalidValue(format!(
+                "motd is longer than {} characters",
+                SERVER_MOTD_LEN_MAX
+            )))?
+        }
+        if self.banned.iter().any(|entry| entry.trim().is_empty()) {
+            Err(ConfigError::InvalidValue(
+                "banned must not contain empty entries".to_string(),
+            ))?
+        }
+        Ok(())
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind_address, self.port)
+    }
+
+    /// Lobby settings a new game starts with.
+    pub fn game_settings(&self) -> GameSettings {
+        GameSettings {
+            mode: self.default_mode,
+            player_count: self.max_players,
+            snake_ticks_per_move: self.default_ticks_per_move,
+            tick_rate: self.tick_rate,
+            ..GameSettings::default()
+        }
+    }
+
+    /// Whether a player with this nickname, connecting from `ip`, is banned.
+    pub fn is_banned(&self, nickname: &str, ip: Option<&str>) -> bool {
+        self.banned
+            .iter()
+            .any(|entry| entry == nickname || Some(entry.as_str()) == ip)
+    }
+
+    /// Takes over the settings of `new` that can change while the server
+    /// runs. Returns a line for every setting that changed and one for every
+    /// change left out because it needs a restart.
+    pub fn reload(&mut self, new: ServerConfig) -> (Vec<String>, Vec<String>) {
+        let mut ignored = Vec::new();
+        push_change(
+            &mut ignored,
+            "bind_address",
+            &self.bind_address,
+            &new.bind_address,
+        );
+        push_change(&mut ignored, "port", &self.port, &new.port);
+        push_change(&mut ignored, "tick_rate", &self.tick_rate, &new.tick_rate);
+        push_change(
+            &mut ignored,
+            "max_players",
+            &self.max_players,
+            &new.max_players,
+        );
+        push_change(
+            &mut ignored,
+            "maps_dir",
+            &self.maps_dir.display().to_string(),
+            &new.maps_dir.display().to_string(),
+        );
+        push_change(
+            &mut ignored,
+            "map",
+            &describe_name(&self.map),
+            &describe_name(&new.map),
+        );
+
+        let mut applied = Vec::new();
+        push_change(
+            &mut applied,
+            "default_mode",
+            &self.default_mode.name(),
+            &new.default_mode.name(),
+        );
+        push_change(
+            &mut applied,
+            "default_ticks_per_move",
+            &self.default_ticks_per_move,
+            &new.default_ticks_per_move,
+        );
+        push_change(
+            &mut applied,
+            "motd",
+            &describe_name(&self.motd),
+            &describe_name(&new.motd),
+        );
+        let added: Vec<&str> = new
+            .banned
+            .iter()
+            .filter(|entry| !self.banned.contains(entry))
+            .map(String::as_str)
+            .collect();
+        if !added.is_empty() {
+            applied.push(format!("banned: added {}", added.join(", ")));
+        }
+        let removed: Vec<&str> = self
+            .banned
+            .iter()
+            .filter(|entry| !new.banned.contains(entry))
+            .map(String::as_str)
+            .collect();
+        if !removed.is_empty() {
+            applied.push(format!("banned: removed {}", removed.join(", ")));
+        }
+        push_change(
+            &mut applied,
+            "input_rate_limit",
+            &self.input_rate_limit,
+            &new.input_rate_limit,
+        );
+        push_change(
+            &mut applied,
+            "join_rate_limit",
+            &self.join_rate_limit,
+            &new.join_rate_limit,
+        );
+
+        self.default_mode = new.default_mode;
+        self.default_ticks_per_move = new.default_ticks_per_move;
+        self.motd = new.motd;
+        self.banned = new.banned;
+        self.input_rate_limit = new.input_rate_limit;
+        self.join_rate_limit = new.join_rate_limit;
+
+        (applied, ignored)
+    }
+
+    pub fn print(&self) {
+        println!("[INFO]: server config:");
+        println!("[INFO]:   address        {}", self.address());
+        println!("[INFO]:   tick rate      {} ticks/s", self.tick_rate);
+        println!("[INFO]:   max players    {}", self.max_players);
+        println!("[INFO]:   default mode   {}", self.default_mode.name());
+        println!(
+            "[INFO]:   default speed  {} ticks per move",
+            self.default_ticks_per_move
+        );
+        println!("[INFO]:   maps dir       {}", self.maps_dir.display());
+        println!("[INFO]:   map            {}", describe_name(&self.map));
+        println!("[INFO]:   motd           {}", describe_name(&self.motd));
+        println!("[INFO]:   banned         {:?}", self.banned);
+        println!(
+            "[INFO]:   rate limits    {} input bursts/s, {} joins/min (0 is unlimited)",
+            self.input_rate_limit, self.join_rate_limit
+        );
+    }
+}
+
+fn push_change<T: PartialEq + std::fmt::Display + ?Sized>(
+    changes: &mut Vec<String>,
+    name: &str,
+    old: &T,
+    new: &T,
+) {
+    if old != new {
+        changes.push(format!("{}: {} -> {}", name, old, new));
+    }
+}
+
+fn describe_name(name: &Option<String>) -> String {
+    match name {
+        Some(name) => format!("{:?}", name),
+        None => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Writes `json` to the config file and moves its modification time on,
+    /// as a coarse file system clock could otherwise hide the change.
+    fn write_config(path: &Path, json: &str, modified: SystemTime) {
+        fs::write(path, json).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn edited_config_file_is_reloaded() {
+        let dir = std::env::temp_dir().join(format!("snake_config_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.json");
+        let start = SystemTime::now();
+        write_config(
+            &path,
+            r#"{ "port": 4000, "motd": "hello", "input_rate_limit": 20 }"#,
+            start,
+        );
+
+        let mut overrides = ConfigOverrides::default();
+        overrides
+            .set_option("motd", "from the command line")
+            .unwrap();
+        let mut source = ConfigSource::new(Some(path.clone()), overrides);
+        let mut config = source.load().unwrap();
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.motd.as_deref(), Some("from the command line"));
+        assert_eq!(config.input_rate_limit, 20);
+        assert!(!source.has_changed());
+
+        write_config(
+            &path,
+            r#"{ "port": 4001, "motd": "bye", "input_rate_limit": 5, "banned": ["mallory"] }"#,
+            start + Duration::from_secs(10),
+        );
+        assert!(source.has_changed());
+        let new = source.load();
+        let changed_after_load = source.has_changed();
+        fs::remove_dir_all(&dir).unwrap();
+        let new = new.unwrap();
+        assert!(!changed_after_load);
+        // the command line still wins over the file
+        assert_eq!(new.motd.as_deref(), Some("from the command line"));
+
+        let (applied, ignored) = config.reload(new);
+        assert_eq!(
+            applied,
+            vec!["banned: added mallory", "input_rate_limit: 20 -> 5"]
+        );
+        assert_eq!(ignored, vec!["port: 4000 -> 4001"]);
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.input_rate_limit, 5);
+        assert!(config.is_banned("mallory", None));
+    }
+
+    #[test]
+    fn invalid_config_file_is_not_loaded() {
+        let dir =
+            std::env::temp_dir().join(format!("snake_config_invalid_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.json");
+        write_config(&path, r#"{ "tick_rate": 0 }"#, SystemTime::now());
+
+        let mut source = ConfigSource::new(Some(path), ConfigOverrides::default());
+        let result = source.load();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
+    }
+}
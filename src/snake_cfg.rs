@@ -1,9 +1,8 @@
-use macroquad::color::{GREEN, PINK};
+use macroquad::color::{BEIGE, GREEN, MAGENTA, PINK};
 
 
 pub const FPS: u64 = 60;
 pub const TICK_RATE_FREQ: u64 = 120;
-pub const TICK_RATE_TIME: f64 = 1.0 / TICK_RATE_FREQ as f64;
 
 pub const FRAME_TIME: f64 = 1.0 / FPS as f64;
 pub const SNAKE_UPDATE_FREQ: u64 = 15;
@@ -11,12 +10,60 @@ pub const SNAKE_UPDATE_STEP: u64 = FPS / SNAKE_UPDATE_FREQ;
 
 pub const SNAKE_SIZE: f32 = 20.0;
 pub const SNAKE_TICKS_PER_MOVE: f32 = 20.0;
+pub const SNAKE_TICKS_PER_MOVE_MIN: u32 = 6;
+pub const SNAKE_TICKS_PER_MOVE_MAX: u32 = 40;
+pub const SNAKE_SPEEDUP_LENGTH_STEP: usize = 5;
+pub const SNAKE_SPEEDUP_TIME_STEP_SECS: u64 = 10;
+pub const SNAKE_TURN_QUEUE_LEN: usize = 3;
 
 pub const SCREEN_WIDTH: f32 = 800.0;
 pub const SCREEN_HEIGHT: f32 = 600.0;
 
-pub const SERVER_ADDRESS: &'static str = "0.0.0.0:6969";
+pub const BOARD_WIDTH: i32 = (SCREEN_WIDTH / SNAKE_SIZE) as i32;
+pub const BOARD_HEIGHT: i32 = (SCREEN_HEIGHT / SNAKE_SIZE) as i32;
 
-pub const PLAYER_COUNT_MAX: usize = 2;
+pub const FRUIT_COUNT: usize = 1;
+pub const FRUITS_PER_PLAYER: usize = 0;
+pub const FRUIT_MIN_DISTANCE: i32 = 4;
+pub const FRUIT_SPAWN_ATTEMPTS: usize = 100;
 
-pub const PLAYER_COLORS: [macroquad::color::Color;  PLAYER_COUNT_MAX] = [PINK, GREEN];
+pub const SAFE_ZONE_SHRINK_START_SECS: u64 = 20;
+pub const SAFE_ZONE_SHRINK_INTERVAL_SECS: u64 = 15;
+pub const SAFE_ZONE_SHRINK_STEP: i32 = 2;
+pub const SAFE_ZONE_MIN_WIDTH: i32 = 12;
+pub const SAFE_ZONE_MIN_HEIGHT: i32 = 8;
+
+pub const COUNTDOWN_SECS: u64 = 3;
+
+pub const SCORE_PER_KILL: u32 = 50;
+pub const SCORE_PER_SECOND_ALIVE: u32 = 1;
+
+pub const SERVER_BIND_ADDRESS: &str = "0.0.0.0";
+pub const SERVER_PORT: u16 = 6969;
+pub const SERVER_CONFIG_PATH: &str = "server.json";
+
+pub const SERVER_TICK_RATE_MIN: u64 = 10;
+pub const SERVER_TICK_RATE_MAX: u64 = 1000;
+pub const SERVER_CONFIG_POLL_INTERVAL_SECS: u64 = 1;
+pub const SERVER_MOTD_LEN_MAX: usize = 500;
+pub const SERVER_INPUT_RATE_LIMIT: u32 = 30;
+/// Inputs taken from a player in one tick, enough for a quick double turn.
+pub const SERVER_INPUT_BURST_MAX: usize = 4;
+pub const SERVER_JOIN_RATE_LIMIT: u32 = 10;
+
+pub const MAPS_DIR: &str = "maps";
+
+pub const REPLAYS_DIR: &str = "replays";
+
+pub const CHECKPOINT_PATH: &str = "checkpoint.bin";
+pub const CHECKPOINT_INTERVAL_SECS: u64 = 5;
+/// How long players of a resumed match have to reconnect before they are
+/// dropped from it.
+pub const RESUME_GRACE_SECS: u64 = 60;
+
+pub const PLAYER_COUNT_MAX: usize = 4;
+/// Players a game waits for unless the server is configured otherwise.
+pub const PLAYER_COUNT_DEFAULT: usize = 2;
+
+pub const PLAYER_COLORS: [macroquad::color::Color; PLAYER_COUNT_MAX] =
+    [PINK, GREEN, BEIGE, MAGENTA];
@@ -17,37 +17,60 @@ pub mod client_comms;
 pub mod common;
 pub mod comms;
 pub mod ifc;
+pub mod replay;
+pub mod replay_viewer;
 
 use crate::comms::*;
+use crate::ifc::Message;
 
 use crate::client_comms::*;
+use crate::replay_viewer::ReplayViewer;
 use macroquad::prelude as mcq;
 
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use std::sync::mpsc::channel;
 
-fn setup_screen() {
+async fn setup_screen() {
     mcq::request_new_screen_size(SCREEN_WIDTH, SCREEN_HEIGHT);
-    mcq::next_frame();
+    mcq::next_frame().await;
+}
+
+enum ClientMode {
+    Play(ClientSettings),
+    /// Watch a replay file instead of connecting to a server.
+    Replay(PathBuf),
 }
 
 fn print_help() {
-    println!("<nickname> <server IP address:port>\n");
+    println!("<nickname> <server IP address:port> [--dump-desync <directory>]");
+    println!("--replay <replay file>\n");
 }
 
-fn parse_args() -> Option<ClientSettings> {
+fn parse_args() -> Option<ClientMode> {
     let mut args = env::args();
     args.next().expect("executable name");
 
-    let nickname: String = args.next()?;
+    let first: String = args.next()?;
+    if first == "--replay" {
+        return Some(ClientMode::Replay(PathBuf::from(args.next()?)));
+    }
+
+    let nickname = first;
     let ip: String = args.next()?;
+    let desync_dump_dir = match args.next() {
+        Some(flag) if flag == "--dump-desync" => Some(PathBuf::from(args.next()?)),
+        Some(_) => return None,
+        None => None,
+    };
 
-    Some(ClientSettings {
-        nickname: nickname,
+    Some(ClientMode::Play(ClientSettings {
+        nickname,
         server_ip: ip,
-    })
+        desync_dump_dir,
+    }))
 }
 
 async fn run_drawing(game_lock: Arc<Mutex<GameLocal>>, input_tx: Sender<char>) {
@@ -57,7 +80,10 @@ async fn run_drawing(game_lock: Arc<Mutex<GameLocal>>, input_tx: Sender<char>) {
             if let Some(c) = mcq::get_char_pressed() {
                 input_tx.send(c).unwrap();
             }
-            let game = game_lock.lock().unwrap();
+            let mut game = game_lock.lock().unwrap();
+            if mcq::is_key_pressed(mcq::KeyCode::F3) {
+                game.toggle_debug_overlay();
+            }
             game.draw();
         }
 
@@ -70,6 +96,24 @@ async fn run_drawing(game_lock: Arc<Mutex<GameLocal>>, input_tx: Sender<char>) {
     }
 }
 
+async fn run_replay(mut viewer: ReplayViewer) {
+    let mut frame_started_t: f64 = 0.0;
+    loop {
+        while let Some(c) = mcq::get_char_pressed() {
+            viewer.handle_input(c);
+        }
+        viewer.update(mcq::get_frame_time() as f64);
+        viewer.draw();
+
+        while (macroquad::time::get_time() - frame_started_t) <= FRAME_TIME {
+            thread::sleep(time::Duration::from_micros(500));
+        }
+
+        frame_started_t = macroquad::time::get_time();
+        mcq::next_frame().await
+    }
+}
+
 fn run_connection_thread(
     game_lock: Arc<Mutex<GameLocal>>,
     mut client_comms: ClientComms,
@@ -79,9 +123,13 @@ fn run_connection_thread(
         client_comms
             .connect()
             .expect("failed to connect to the server");
-        client_comms
+        let motd = client_comms
             .join_server()
             .expect("failed to join the server");
+        if let Some(motd) = motd {
+            println!("[INFO]: message of the day: {}", motd);
+            game_lock.lock().unwrap().set_motd(motd);
+        }
 
         loop {
             if let Ok(c) = input_rx.try_recv() {
@@ -92,19 +140,30 @@ fn run_connection_thread(
                 client_comms.send_input(c).unwrap();
             }
 
-            match client_comms.receive_game_update() {
-                Ok(game_update) => {
-                    if let Some(new_game_state) = game_update {
+            // the server may send more than one message per tick
+            loop {
+                match client_comms.receive_server_update() {
+                    Ok(Some(Message::StateHash { match_tick, hash })) => {
                         let mut game = game_lock.lock().unwrap();
-                        game.game_core = new_game_state;
+                        game.check_state_hash(match_tick, hash);
+                    }
+                    Ok(Some(Message::GameUpdate(new_game_state))) => {
+                        let mut game = game_lock.lock().unwrap();
+                        game.set_server_state(*new_game_state);
+                    }
+                    Ok(Some(Message::GameEvents(events))) => {
+                        let mut game = game_lock.lock().unwrap();
+                        game.handle_events(events);
+                    }
+                    Ok(_) => break,
+                    Err(err) => {
+                        eprintln!("error: {:?}", err);
+                        panic!()
                     }
-                }
-                Err(err) => {
-                    eprintln!("error: {:?}", err);
-                    panic!()
                 }
             }
-            thread::sleep(time::Duration::from_secs_f64(TICK_RATE_TIME));
+            let tick_rate = game_lock.lock().unwrap().game_core.settings.tick_rate;
+            thread::sleep(time::Duration::from_secs_f64(1.0 / tick_rate as f64));
         }
     });
 }
@@ -112,11 +171,12 @@ fn run_connection_thread(
 fn run_game_logic_thread(game_lock: Arc<Mutex<GameLocal>>) {
     thread::spawn(move || {
         loop {
-            {
+            let tick_rate = {
                 let mut game = game_lock.lock().unwrap();
                 game.update();
-            }
-            thread::sleep(time::Duration::from_secs_f64(TICK_RATE_TIME));
+                game.game_core.settings.tick_rate
+            };
+            thread::sleep(time::Duration::from_secs_f64(1.0 / tick_rate as f64));
         }
     });
 }
@@ -124,7 +184,19 @@ fn run_game_logic_thread(game_lock: Arc<Mutex<GameLocal>>) {
 #[macroquad::main("Snake")]
 async fn main() -> Result<(), ()> {
     let client_settings = match parse_args() {
-        Some(cs) => cs,
+        Some(ClientMode::Play(cs)) => cs,
+        Some(ClientMode::Replay(path)) => {
+            let viewer = ReplayViewer::open(&path).map_err(|err| {
+                eprintln!(
+                    "[ERROR]: failed to open replay {}: {:?}",
+                    path.display(),
+                    err
+                );
+            })?;
+            setup_screen().await;
+            run_replay(viewer).await;
+            return Ok(());
+        }
         None => {
             print_help();
             return Err(());
@@ -132,9 +204,12 @@ async fn main() -> Result<(), ()> {
     };
 
     client_settings.print();
-    setup_screen();
+    setup_screen().await;
 
-    let game_lock = Arc::new(Mutex::new(GameLocal::new(&client_settings.nickname)));
+    let game_lock = Arc::new(Mutex::new(GameLocal::new(
+        &client_settings.nickname,
+        client_settings.desync_dump_dir.clone(),
+    )));
 
     let (input_tx, input_rx): (Sender<char>, Receiver<char>) = channel();
     run_connection_thread(
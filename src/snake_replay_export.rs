@@ -0,0 +1,216 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub mod game;
+use crate::game::*;
+
+pub mod snake_cfg;
+
+pub mod common;
+pub mod raster;
+pub mod replay;
+
+use crate::game::game_core::GameCore;
+use crate::raster::Canvas;
+use crate::replay::{Replay, ReplayError, ReplayPlayer};
+
+/// Frames per second of play exported when no `--step` is given.
+const DEFAULT_FRAMES_PER_SECOND: u64 = 10;
+const DEFAULT_CELL_SIZE: usize = 10;
+/// Palette quantization speed, only used for frames with over 256 colors.
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Replay(ReplayError),
+    Io(io::Error),
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+}
+
+struct ExportSettings {
+    replay_path: PathBuf,
+    /// A `.gif` file for an animation, otherwise a directory for PNG frames.
+    output: PathBuf,
+    from_tick: u64,
+    to_tick: Option<u64>,
+    /// Ticks between two frames, derived from the replay's tick rate if
+    /// not given.
+    step_ticks: Option<u64>,
+    cell_size: usize,
+}
+
+fn print_help() {
+    println!(
+        "<replay file> <output .gif or directory for .png frames> [--from <tick>] [--to <tick>] [--step <ticks>] [--cell <pixels>]\n"
+    );
+}
+
+fn parse_args() -> Option<ExportSettings> {
+    let mut args = env::args();
+    args.next().expect("executable name");
+
+    let mut settings = ExportSettings {
+        replay_path: PathBuf::from(args.next()?),
+        output: PathBuf::from(args.next()?),
+        from_tick: 0,
+        to_tick: None,
+        step_ticks: None,
+        cell_size: DEFAULT_CELL_SIZE,
+    };
+
+    while let Some(flag) = args.next() {
+        let value = args.next()?;
+        match flag.as_str() {
+            "--from" => settings.from_tick = value.parse().ok()?,
+            "--to" => settings.to_tick = Some(value.parse().ok()?),
+            "--step" => settings.step_ticks = Some(value.parse().ok().filter(|step| *step > 0)?),
+            "--cell" => settings.cell_size = value.parse().ok().filter(|size| *size > 0)?,
+            _ => return None,
+        }
+    }
+    Some(settings)
+}
+
+fn step_ticks(settings: &ExportSettings, game: &GameCore) -> u64 {
+    settings
+        .step_ticks
+        .unwrap_or((game.settings.tick_rate / DEFAULT_FRAMES_PER_SECOND).max(1))
+}
+
+/// Re-simulates the replay and hands a frame of every `step_ticks` ticks in
+/// the range, along with the game it shows, to `write_frame`. Returns the
+/// number of frames.
+fn render_frames(
+    settings: &ExportSettings,
+    mut write_frame: impl FnMut(u64, &GameCore, &Canvas) -> Result<(), ExportError>,
+) -> Result<usize, ExportError> {
+    let mut player =
+        ReplayPlayer::new(Replay::load(&settings.replay_path).map_err(ExportError::Replay)?);
+    let last_tick = player.replay.last_tick();
+    let to_tick = settings
+        .to_tick
+        .map_or(last_tick, |tick| tick.min(last_tick));
+
+    let mut game = player.restart();
+    let mut canvas = Canvas::new(game.map().width, game.map().height, settings.cell_size);
+    let mut frames = 0;
+
+    let mut tick = settings.from_tick;
+    while tick <= to_tick {
+        player.seek(&mut game, tick);
+        if game.match_tick < tick {
+            break;
+        }
+
+        canvas.draw_game(&game);
+        write_frame(tick, &game, &canvas)?;
+        frames += 1;
+        tick += step_ticks(settings, &game);
+    }
+    Ok(frames)
+}
+
+fn export_gif(settings: &ExportSettings) -> Result<usize, ExportError> {
+    let file = File::create(&settings.output).map_err(ExportError::Io)?;
+
+    let mut encoder: Option<gif::Encoder<BufWriter<File>>> = None;
+    let mut file = Some(BufWriter::new(file));
+    let frames = render_frames(settings, |_tick, game, canvas| {
+        let (Ok(width), Ok(height)) = (u16::try_from(canvas.width), u16::try_from(canvas.height))
+        else {
+            return Err(ExportError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image too large for a GIF",
+            )));
+        };
+
+        if encoder.is_none() {
+            let writer = file.take().expect("file is only taken once");
+            let mut new_encoder =
+                gif::Encoder::new(writer, width, height, &[]).map_err(ExportError::Gif)?;
+            new_encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(ExportError::Gif)?;
+            encoder = Some(new_encoder);
+        }
+        let encoder = encoder.as_mut().expect("gif encoder was just created");
+
+        let mut frame = gif::Frame::from_rgb_speed(
+            width,
+            height,
+            canvas.pixels.as_flattened(),
+            GIF_QUANTIZE_SPEED,
+        );
+        // in hundredths of a second; viewers slow down anything under 2
+        frame.delay = (step_ticks(settings, game) * 100 / game.settings.tick_rate)
+            .clamp(2, u16::MAX as u64) as u16;
+        encoder.write_frame(&frame).map_err(ExportError::Gif)
+    })?;
+
+    if let Some(encoder) = encoder {
+        let mut writer = encoder.into_inner().map_err(ExportError::Io)?;
+        writer.flush().map_err(ExportError::Io)?;
+    }
+    Ok(frames)
+}
+
+fn write_png(path: &Path, canvas: &Canvas) -> Result<(), ExportError> {
+    let file = File::create(path).map_err(ExportError::Io)?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        canvas.width as u32,
+        canvas.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(ExportError::Png)?;
+    writer
+        .write_image_data(canvas.pixels.as_flattened())
+        .map_err(ExportError::Png)
+}
+
+fn export_pngs(settings: &ExportSettings) -> Result<usize, ExportError> {
+    fs::create_dir_all(&settings.output).map_err(ExportError::Io)?;
+    render_frames(settings, |tick, _game, canvas| {
+        write_png(
+            &settings.output.join(format!("tick_{:06}.png", tick)),
+            canvas,
+        )
+    })
+}
+
+fn main() -> Result<(), ()> {
+    let Some(settings) = parse_args() else {
+        print_help();
+        return Err(());
+    };
+
+    let is_gif = settings
+        .output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+    let result = if is_gif {
+        export_gif(&settings)
+    } else {
+        export_pngs(&settings)
+    };
+
+    match result {
+        Ok(frames) => {
+            println!(
+                "[INFO]: wrote {} frames to {}",
+                frames,
+                settings.output.display()
+            );
+            Ok(())
+        }
+        Err(err) => {
+            eprintln!("[ERROR]: export failed: {:?}", err);
+            Err(())
+        }
+    }
+}
@@ -1,8 +1,13 @@
-use game::game_core::GameCore;
+use game::game_core::{GameCore, GameState};
+use game::game_mode::GameModeKind;
+use game::map::Map;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
 use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex};
 use std::{io, thread, time};
 
@@ -20,26 +25,325 @@ use crate::game::*;
 
 pub mod common;
 
+pub mod replay;
+use crate::replay::*;
+
+pub mod checkpoint;
+use crate::checkpoint::*;
+
+pub mod server_config;
+use crate::server_config::*;
+
+pub mod rate_limit;
+use crate::rate_limit::RateLimiter;
+
 enum ServerState {
     WaitingForPlayers,
     Running,
 }
 
+/// The game together with the replay of the match being played, shared by
+/// the network loop and the game thread.
+struct ServerGame {
+    core: GameCore,
+    replay: Option<ReplayRecorder>,
+    /// Seed of the last match a replay was started for.
+    replay_seed: Option<u64>,
+    ticks_since_checkpoint: u64,
+    checkpoint_interval_ticks: u64,
+    /// Lobby defaults changed by a config reload, applied once no match is
+    /// being played.
+    pending_mode: Option<GameModeKind>,
+    pending_ticks_per_move: Option<u32>,
+}
+
+impl ServerGame {
+    fn new(core: GameCore, tick_rate: u64) -> Self {
+        Self {
+            core,
+            replay: None,
+            replay_seed: None,
+            ticks_since_checkpoint: 0,
+            checkpoint_interval_ticks: CHECKPOINT_INTERVAL_SECS * tick_rate,
+            pending_mode: None,
+            pending_ticks_per_move: None,
+        }
+    }
+
+    /// Picks up the game saved in a checkpoint. Its replay can't be
+    /// continued, as it doesn't know about the ticks lost in the crash.
+    fn resumed(core: GameCore, tick_rate: u64) -> Self {
+        let seed = core.seed;
+        Self {
+            replay_seed: Some(seed),
+            ..Self::new(core, tick_rate)
+        }
+    }
+
+    fn update(&mut self) {
+        self.apply_pending_defaults();
+        self.core.update();
+        self.update_replay();
+
+        self.ticks_since_checkpoint += 1;
+        if self.ticks_since_checkpoint >= self.checkpoint_interval_ticks {
+            self.ticks_since_checkpoint = 0;
+            if let Err(err) = Checkpoint::save(Path::new(CHECKPOINT_PATH), &self.core) {
+                eprintln!("[ERROR] failed to save checkpoint: {:?}", err);
+            }
+        }
+    }
+
+    /// Changes the lobby settings for the matches to come. A match in
+    /// progress, including one between rounds, keeps what it started with.
+    fn set_defaults(&mut self, mode: Option<GameModeKind>, ticks_per_move: Option<u32>) {
+        self.pending_mode = mode.or(self.pending_mode);
+        self.pending_ticks_per_move = ticks_per_move.or(self.pending_ticks_per_move);
+        self.apply_pending_defaults();
+    }
+
+    fn apply_pending_defaults(&mut self) {
+        if !self.core.is_between_matches() {
+            return;
+        }
+
+        if let Some(mode) = self.pending_mode.take() {
+            println!("[INFO]: new matches use mode {}", mode.name());
+            self.core.settings.mode = mode;
+        }
+        if let Some(ticks_per_move) = self.pending_ticks_per_move.take() {
+            println!("[INFO]: new matches use {} ticks per move", ticks_per_move);
+            self.core.settings.snake_ticks_per_move = ticks_per_move;
+        }
+    }
+
+    fn handle_input(&mut self, player_name: &str, c: char) {
+        self.core.handle_input(player_name, c);
+        self.record_input(player_name, ReplayInput::Key(c));
+        self.update_replay();
+    }
+
+    fn remove_player(&mut self, player_name: &str) {
+        self.core.remove_player(player_name);
+        self.record_input(player_name, ReplayInput::Left);
+        self.update_replay();
+    }
+
+    fn record_input(&mut self, player_name: &str, input: ReplayInput) {
+        if let Some(replay) = &mut self.replay
+            && let Err(err) = replay.record_input(&self.core, player_name, input)
+        {
+            eprintln!(
+                "[ERROR] failed to write replay {}: {:?}",
+                replay.path.display(),
+                err
+            );
+            self.replay = None;
+        }
+    }
+
+    /// Starts a replay when a new match starts and finishes it with the
+    /// result once the match is over.
+    fn update_replay(&mut self) {
+        let match_over = match &self.core.state {
+            GameState::NotStarted => return,
+            GameState::Finished(details) => details.match_over,
+            _ => false,
+        };
+
+        if match_over {
+            if let GameState::Finished(details) = &self.core.state
+                && let Some(mut replay) = self.replay.take()
+            {
+                match replay.record_result(&self.core, details) {
+                    Ok(()) => println!("[INFO]: replay saved to {}", replay.path.display()),
+                    Err(err) => eprintln!(
+                        "[ERROR] failed to write replay {}: {:?}",
+                        replay.path.display(),
+                        err
+                    ),
+                }
+            }
+        } else if self.replay_seed != Some(self.core.seed) {
+            self.replay_seed = Some(self.core.seed);
+            self.replay = match ReplayRecorder::create(Path::new(REPLAYS_DIR), &self.core) {
+                Ok(replay) => {
+                    println!("[INFO]: recording replay to {}", replay.path.display());
+                    Some(replay)
+                }
+                Err(err) => {
+                    eprintln!("[ERROR] failed to start replay: {:?}", err);
+                    None
+                }
+            };
+        }
+    }
+}
+
 struct Server {
+    config: ServerConfig,
+    config_source: ConfigSource,
+    last_config_poll: time::Instant,
+    /// Lines typed on the server's standard input.
+    commands: Receiver<String>,
+    input_limiter: RateLimiter,
+    join_limiter: RateLimiter,
     state: ServerState,
-    game_guard: Arc<Mutex<GameCore>>,
+    game_guard: Arc<Mutex<ServerGame>>,
     player_comms: HashMap<String, Rc<RefCell<Comms>>>,
+    /// When players of a resumed match that haven't reconnected are given
+    /// up on.
+    resume_deadline: Option<time::Instant>,
 }
 
 impl Server {
-    fn try_add_player(&mut self, nickname: &str, comms: Rc<RefCell<Comms>>) -> Result<(), String> {
+    fn new(
+        config: ServerConfig,
+        config_source: ConfigSource,
+        game_guard: Arc<Mutex<ServerGame>>,
+    ) -> Self {
+        // players already in the game come from a checkpoint
+        let resuming = !game_guard.lock().unwrap().core.players.is_empty();
+        let grace = time::Duration::from_secs(RESUME_GRACE_SECS);
+
+        Self {
+            resume_deadline: resuming.then(|| time::Instant::now() + grace),
+            input_limiter: RateLimiter::new(config.input_rate_limit, time::Duration::from_secs(1)),
+            join_limiter: RateLimiter::new(config.join_rate_limit, time::Duration::from_secs(60)),
+            config,
+            config_source,
+            last_config_poll: time::Instant::now(),
+            commands: launch_command_thread(),
+            state: ServerState::WaitingForPlayers,
+            game_guard,
+            player_comms: HashMap::new(),
+        }
+    }
+
+    /// Reloads the config when its file changed or a reload was asked for.
+    fn handle_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command.trim() {
+                "reload" => self.reload_config(),
+                "" => {}
+                other => eprintln!("[WARNING]: unknown command '{}', try: reload", other),
+            }
+        }
+
+        let poll_interval = time::Duration::from_secs(SERVER_CONFIG_POLL_INTERVAL_SECS);
+        if self.last_config_poll.elapsed() >= poll_interval {
+            self.last_config_poll = time::Instant::now();
+            if self.config_source.has_changed() {
+                println!(
+                    "[INFO]: {} changed, reloading",
+                    self.config_source.path().display()
+                );
+                self.reload_config();
+            }
+        }
+    }
+
+    fn reload_config(&mut self) {
+        let config = match self.config_source.load() {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!(
+                    "[ERROR]: failed to reload {}, keeping the current config: {:?}",
+                    self.config_source.path().display(),
+                    err
+                );
+                return;
+            }
+        };
+
+        let old_mode = self.config.default_mode;
+        let old_ticks_per_move = self.config.default_ticks_per_move;
+        let (applied, ignored) = self.config.reload(config);
+
+        for change in &ignored {
+            eprintln!("[WARNING]: config: {} needs a restart, ignored", change);
+        }
+        for change in &applied {
+            println!("[INFO]: config: {}", change);
+        }
+        if applied.is_empty() && ignored.is_empty() {
+            println!("[INFO]: config reloaded, nothing changed");
+        }
+
+        self.input_limiter.set_limit(self.config.input_rate_limit);
+        self.join_limiter.set_limit(self.config.join_rate_limit);
+
+        let mode = Some(self.config.default_mode).filter(|mode| *mode != old_mode);
+        let ticks_per_move = Some(self.config.default_ticks_per_move)
+            .filter(|ticks_per_move| *ticks_per_move != old_ticks_per_move);
+        if mode.is_some() || ticks_per_move.is_some() {
+            let mut game_guard = self.game_guard.lock().unwrap();
+            game_guard.set_defaults(mode, ticks_per_move);
+        }
+    }
+
+    /// Gives up on the players of a resumed match that didn't reconnect in
+    /// time, so the server doesn't stay closed to everyone else.
+    fn drop_absent_players(&mut self) {
+        match self.resume_deadline {
+            Some(deadline) if time::Instant::now() >= deadline => self.resume_deadline = None,
+            _ => return,
+        }
+
+        let mut game_guard = self.game_guard.lock().unwrap();
+        let absent: Vec<String> = game_guard
+            .core
+            .players
+            .keys()
+            .filter(|name| !self.player_comms.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in absent {
+            eprintln!(
+                "[WARNING]: {} didn't reconnect within {} s, removing player",
+                name, RESUME_GRACE_SECS
+            );
+            game_guard.remove_player(&name);
+        }
+    }
+
+    /// Adds a player to the game, or lets a player of a resumed match back
+    /// in. A returning player is recognized by nickname alone, so anyone
+    /// connecting first with that nickname takes the place.
+    fn try_add_player(
+        &mut self,
+        nickname: &str,
+        ip: Option<&str>,
+        comms: Rc<RefCell<Comms>>,
+    ) -> Result<(), String> {
+        if self.config.is_banned(nickname, ip) {
+            Err(format!("Player '{}' is banned from this server", nickname))?
+        }
         if self.player_comms.contains_key(nickname) {
-            Err(format!("Player {} is already added", nickname))?
+            Err(format!("Player '{}' is already added", nickname))?
         }
 
         {
             let mut game_guard = self.game_guard.lock().unwrap();
-            game_guard.add_player(&nickname);
+            // players of a resumed match are still in the game, waiting
+            let awaited: Vec<&String> = game_guard
+                .core
+                .players
+                .keys()
+                .filter(|name| !self.player_comms.contains_key(*name))
+                .collect();
+
+            if awaited.iter().any(|name| *name == nickname) {
+                println!("[INFO]: player {} is back", nickname);
+            } else if !awaited.is_empty() {
+                let names: Vec<&str> = awaited.iter().map(|name| name.as_str()).collect();
+                Err(format!("Waiting for {} to reconnect", names.join(", ")))?
+            } else if !game_guard.core.is_between_matches() {
+                // a late joiner would have no snake but count as alive
+                Err("A match is in progress, try again once it is over".to_string())?
+            } else {
+                game_guard.core.add_player(nickname);
+            }
         }
 
         self.player_comms.insert(nickname.to_string(), comms);
@@ -57,6 +361,7 @@ impl Server {
 
     fn handle_connection(&mut self, stream: TcpStream) -> Result<(), CommError> {
         println!("[SERVER]: client connecting {:?}...", stream.peer_addr());
+        let ip = stream.peer_addr().ok().map(|addr| addr.ip().to_string());
         let comms_rc = Rc::new(RefCell::new(Comms::new(Some(stream))));
 
         let msg: Message = comms_rc.borrow_mut().receive_message()?;
@@ -68,12 +373,18 @@ impl Server {
             player_name: nickname,
         } = msg
         {
-            resp = Message::Ok;
+            resp = match &self.config.motd {
+                Some(motd) => Message::Welcome { motd: motd.clone() },
+                None => Message::Ok,
+            };
 
-            let result = self.try_add_player(nickname.as_str(), comms_rc.clone());
+            let result = if ip.as_deref().is_some_and(|ip| !self.join_limiter.allow(ip)) {
+                Err("Too many join attempts, try again later".to_string())
+            } else {
+                self.try_add_player(nickname.as_str(), ip.as_deref(), comms_rc.clone())
+            };
 
-            if result.is_err() {
-                let msg = format!("Player '{}' is already added", nickname.as_str());
+            if let Err(msg) = result {
                 eprintln!("[WARNING]: {}", msg);
                 resp = Message::Nok { error_msg: msg };
             } else {
@@ -89,23 +400,44 @@ impl Server {
     }
 
     fn send_update(&mut self) {
-        let game_copy: GameCore = {
-            let game_guard = self.game_guard.lock().unwrap();
-            game_guard.clone()
+        let (game_copy, events) = {
+            let mut game_guard = self.game_guard.lock().unwrap();
+            (game_guard.core.clone(), game_guard.core.drain_events())
         };
 
+        for event in &events {
+            println!("[INFO]: event {:?}", event);
+        }
+
+        let mut messages = vec![
+            Message::StateHash {
+                match_tick: game_copy.match_tick,
+                hash: game_copy.state_hash(),
+            },
+            Message::GameUpdate(Box::new(game_copy)),
+        ];
+        if !events.is_empty() {
+            messages.push(Message::GameEvents(events));
+        }
+
         let mut disconnected_players: Vec<String> = Vec::new();
 
         for (player_name, player_rc) in &self.player_comms {
-            let _ = player_rc
-                .borrow_mut()
-                .send_message(&Message::GameUpdate(game_copy.clone()))
-                .inspect_err(|err| match err {
-                    CommError::WouldBlock => {}
-                    _ => {
+            for message in &messages {
+                match player_rc.borrow_mut().send_message(message) {
+                    Ok(()) | Err(CommError::WouldBlock) => {}
+                    Err(CommError::MessageTooLarge(size)) => {
+                        eprintln!(
+                            "[ERROR]: message of {} bytes is too large to send, dropped",
+                            size
+                        );
+                    }
+                    Err(_) => {
                         disconnected_players.push(player_name.clone());
+                        break;
                     }
-                });
+                }
+            }
         }
 
         for player in disconnected_players {
@@ -117,23 +449,49 @@ impl Server {
         let mut disconnected_players: Vec<String> = Vec::new();
 
         for (player_name, comms_rc) in &self.player_comms {
-            let message = comms_rc.borrow_mut().receive_message();
-            match message {
-                Ok(message) => match message {
-                    Message::SendInput(c) => {
-                        let mut game = self.game_guard.lock().unwrap();
-                        game.handle_input(player_name.as_str(), c);
-                    }
-                    _ => {
-                        eprintln!("[ERROR] unexpected message received");
-                    }
-                },
-                Err(e) => match e {
-                    CommError::WouldBlock => {}
-                    _ => {
-                        disconnected_players.push(player_name.clone());
+            // the inputs of one tick count once against the rate limit, as
+            // a burst of keypresses is what a quick turn looks like
+            let mut within_limit = None;
+            let mut accepted = 0;
+            let mut dropped = 0;
+
+            // everything that arrived since the last call
+            loop {
+                let message = comms_rc.borrow_mut().receive_message();
+                match message {
+                    Ok(message) => match message {
+                        Message::SendInput(c) => {
+                            let within_limit = *within_limit
+                                .get_or_insert_with(|| self.input_limiter.allow(player_name));
+                            if !within_limit || accepted >= SERVER_INPUT_BURST_MAX {
+                                dropped += 1;
+                                continue;
+                            }
+                            accepted += 1;
+                            let mut game = self.game_guard.lock().unwrap();
+                            game.handle_input(player_name.as_str(), c);
+                        }
+                        _ => {
+                            eprintln!("[ERROR] unexpected message received");
+                        }
+                    },
+                    Err(e) => {
+                        match e {
+                            CommError::WouldBlock => {}
+                            _ => {
+                                disconnected_players.push(player_name.clone());
+                            }
+                        }
+                        break;
                     }
-                },
+                }
+            }
+
+            if dropped > 0 {
+                eprintln!(
+                    "[WARNING]: dropped {} inputs from {}, over the rate limit",
+                    dropped, player_name
+                );
             }
         }
 
@@ -143,20 +501,22 @@ impl Server {
     }
 
     fn main_loop(&mut self) -> io::Result<()> {
-        let listener = TcpListener::bind(SERVER_ADDRESS).inspect_err(|err| {
-            eprintln!(
-                "[ERROR]: failed to bind to address {}: {}",
-                SERVER_ADDRESS, err
-            )
+        let address = self.config.address();
+        let listener = TcpListener::bind(&address).inspect_err(|err| {
+            eprintln!("[ERROR]: failed to bind to address {}: {}", address, err)
         })?;
 
         listener.set_nonblocking(true).inspect_err(|err| {
             eprintln!("[ERROR]: failed to set nonblocking on socket {}", err);
         })?;
 
-        println!("[INFO]: Listening for connection at {}...", SERVER_ADDRESS);
+        println!("[INFO]: Listening for connection at {}...", address);
+
+        let tick_time = time::Duration::from_secs_f64(1.0 / self.config.tick_rate as f64);
 
         loop {
+            self.handle_commands();
+            self.drop_absent_players();
             self.send_update();
 
             match self.state {
@@ -165,8 +525,13 @@ impl Server {
                     match result {
                         Ok((stream, _socket_addr)) => {
                             let _ = self.handle_connection(stream);
-                            if self.player_comms.iter().count() == 2 {
-                                println!("2 players joined the lobby, starting the game");
+                            let player_count =
+                                self.game_guard.lock().unwrap().core.settings.player_count;
+                            if self.player_comms.len() >= player_count {
+                                println!(
+                                    "{} players joined the lobby, starting the game",
+                                    player_count
+                                );
                                 self.state = ServerState::Running;
                             }
                         }
@@ -185,12 +550,27 @@ impl Server {
                     self.receive_messages();
                 }
             }
-            thread::sleep(time::Duration::from_secs_f64(TICK_RATE_TIME));
+            thread::sleep(tick_time);
         }
     }
 }
 
-fn launch_game_update_thread(game_guard: Arc<Mutex<GameCore>>) {
+/// Forwards the lines typed on standard input, the server's commands.
+fn launch_command_thread() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn launch_game_update_thread(game_guard: Arc<Mutex<ServerGame>>, tick_rate: u64) {
+    let tick_time = time::Duration::from_secs_f64(1.0 / tick_rate as f64);
     std::thread::spawn(move || {
         println!("[INFO]: starting game thread...");
 
@@ -200,21 +580,164 @@ fn launch_game_update_thread(game_guard: Arc<Mutex<GameCore>>) {
                 game.update();
             }
 
-            thread::sleep(time::Duration::from_secs_f64(TICK_RATE_TIME));
+            thread::sleep(tick_time);
         }
     });
 }
 
+/// Plays on the map called `name`, or on the first one in `maps_dir`.
+fn load_map(game: &mut GameCore, maps_dir: &Path, name: Option<&str>) -> Result<(), String> {
+    let maps = Map::load_dir(maps_dir);
+    for map in &maps {
+        println!(
+            "[INFO]: loaded map '{}' ({}x{})",
+            map.name, map.width, map.height
+        );
+    }
+
+    let names: Vec<String> = maps.iter().map(|map| map.name.clone()).collect();
+    let map = match name {
+        Some(name) => maps
+            .into_iter()
+            .find(|map| map.name == name)
+            .ok_or_else(|| {
+                format!(
+                    "no map '{}' in '{}', found: {}",
+                    name,
+                    maps_dir.display(),
+                    names.join(", ")
+                )
+            })?,
+        None => match maps.into_iter().next() {
+            Some(map) => map,
+            None => {
+                println!(
+                    "[INFO]: no maps found in '{}', using empty board",
+                    maps_dir.display()
+                );
+                return Ok(());
+            }
+        },
+    };
+
+    println!("[INFO]: using map '{}'", map.name);
+    game.set_map(map);
+    Ok(())
+}
+
+fn print_help() {
+    println!(
+        "[--resume] [--config <file>] [--bind <address>] [--port <port>] [--tick-rate <ticks/s>] [--max-players <count>] [--mode <classic|battle-royale|light-cycle>] [--ticks-per-move <ticks>] [--maps <dir>] [--map <name>] [--motd <text>]\n"
+    );
+}
+
+struct ServerArgs {
+    /// Whether to resume from the last checkpoint.
+    resume: bool,
+    config_path: Option<PathBuf>,
+    overrides: ConfigOverrides,
+}
+
+fn parse_args() -> Result<ServerArgs, ConfigError> {
+    let mut parsed = ServerArgs {
+        resume: false,
+        config_path: None,
+        overrides: ConfigOverrides::default(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--resume" {
+            parsed.resume = true;
+            continue;
+        }
+
+        let Some(option) = arg.strip_prefix("--") else {
+            Err(ConfigError::InvalidArgument(format!(
+                "unexpected argument '{}'",
+                arg
+            )))?
+        };
+        let value = args
+            .next()
+            .ok_or_else(|| ConfigError::InvalidArgument(format!("--{} expects a value", option)))?;
+        match option {
+            "config" => parsed.config_path = Some(PathBuf::from(value)),
+            _ => parsed.overrides.set_option(option, &value)?,
+        }
+    }
+    Ok(parsed)
+}
+
+fn resume_game(tick_rate: u64) -> Result<ServerGame, CheckpointError> {
+    let checkpoint = Checkpoint::load(Path::new(CHECKPOINT_PATH))?;
+    println!(
+        "[INFO]: resuming from checkpoint saved at {} (unix time)",
+        checkpoint.saved_at
+    );
+
+    // every duration in the saved game is counted in ticks of its own rate
+    let saved = checkpoint.game.settings.tick_rate;
+    if saved != tick_rate {
+        Err(CheckpointError::TickRateMismatch {
+            saved,
+            configured: tick_rate,
+        })?
+    }
+
+    let game = checkpoint.into_game();
+    let players: Vec<&str> = game.players.keys().map(String::as_str).collect();
+    if !players.is_empty() {
+        println!(
+            "[INFO]: waiting up to {} s for {} to reconnect",
+            RESUME_GRACE_SECS,
+            players.join(", ")
+        );
+    }
+    Ok(ServerGame::resumed(game, tick_rate))
+}
+
 fn main() -> Result<(), ()> {
-    let game_guard = Arc::new(Mutex::new(GameCore::new(true)));
+    let args = parse_args().map_err(|err| {
+        eprintln!("[ERROR]: {:?}", err);
+        print_help();
+    })?;
+
+    let mut config_source = ConfigSource::new(args.config_path, args.overrides);
+    let config = config_source.load().map_err(|err| {
+        eprintln!("[ERROR]: invalid server config: {:?}", err);
+    })?;
+    let config_path = config_source.path().display();
+    if config_source.path().exists() {
+        println!("[INFO]: read config from {}", config_path);
+    } else {
+        println!("[INFO]: no config file at {}, using defaults", config_path);
+    }
+    println!(
+        "[INFO]: edit the config file or type 'reload' to change the defaults, motd, bans and rate limits"
+    );
+    config.print();
 
-    let mut server = Server {
-        state: ServerState::WaitingForPlayers,
-        game_guard: game_guard.clone(),
-        player_comms: HashMap::new(),
+    let game = if args.resume {
+        resume_game(config.tick_rate).map_err(|err| {
+            eprintln!(
+                "[ERROR]: failed to resume from {}: {:?}",
+                CHECKPOINT_PATH, err
+            );
+        })?
+    } else {
+        let mut game = GameCore::new(true);
+        println!("[INFO]: game seed {}", game.seed);
+        game.set_settings(config.game_settings());
+        load_map(&mut game, &config.maps_dir, config.map.as_deref())
+            .map_err(|err| eprintln!("[ERROR]: {}", err))?;
+        ServerGame::new(game, config.tick_rate)
     };
+    let game_guard = Arc::new(Mutex::new(game));
+
+    launch_game_update_thread(game_guard.clone(), config.tick_rate);
 
-    launch_game_update_thread(game_guard.clone());
+    let mut server = Server::new(config, config_source, game_guard.clone());
 
     server.main_loop().map_err(|_err| {
         eprintln!("[ERROR]: failed to start main loop");